@@ -0,0 +1,719 @@
+//! Typed builder for `InvokeRequest`.
+//!
+//! Builder hands out handles for files, pipes, volumes, sandboxes and
+//! commands. Handles can only be obtained from the builder, so references
+//! to undefined objects can not be expressed. Using a handle with another
+//! builder is a programming error and causes panic.
+//!
+//! Stages are assigned automatically: command is placed in a later stage
+//! than commands it was ordered after (see `CommandBuilder::after`),
+//! commands sharing a pipe are placed in the same stage, volumes are
+//! created in the first stage, and every other object is created in the
+//! earliest stage where it is used.
+//!
+//! ```
+//! # use invoker_api::{builder::RequestBuilder, invoke::Limits};
+//! let mut b = RequestBuilder::new(uuid::Uuid::nil());
+//! let sandbox = b.sandbox(Limits::new(256 << 20, 1000), "/").add();
+//! let source = b.input_string("int main() {}");
+//! let log = b.create_file();
+//! let compile = b
+//!     .command(&sandbox, vec!["gcc", "-x", "c", "-"])
+//!     .stdin(&source)
+//!     .stderr(&log)
+//!     .add();
+//! b.command(&sandbox, vec!["./a.out"])
+//!     .only_if_succeeded(&compile)
+//!     .add();
+//! b.output_file("compile-log", &log);
+//! let req = b.build().unwrap();
+//! assert!(invoker_api::validate::validate(&req).is_empty());
+//! ```
+
+use crate::{
+    error::{ApiError, ErrorCode},
+    invoke::{
+        Action, Budget, Command, EnvVarValue, EnvironmentVariable, Extensions, FileId, Input,
+        InputSource, InvokeRequest, Limits, OutputRequest, OutputRequestTarget, PathPrefix,
+        PrefixedPath, SandboxSettings, SharedDir, SharedDirectoryMode, Step, StepCondition, Stdio,
+        VolumeSettings,
+    },
+};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_BUILDER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Handle of a file-like object (input, file or pipe half).
+#[derive(Debug, Clone)]
+pub struct FileHandle {
+    builder: u64,
+    id: FileId,
+    /// Index of the step creating this file. Not set for inputs.
+    resource: Option<usize>,
+}
+
+impl FileHandle {
+    pub fn id(&self) -> &FileId {
+        &self.id
+    }
+}
+
+/// Both halves of a pipe.
+#[derive(Debug, Clone)]
+pub struct Pipe {
+    pub read: FileHandle,
+    pub write: FileHandle,
+}
+
+/// Handle of a volume.
+#[derive(Debug, Clone)]
+pub struct VolumeHandle {
+    builder: u64,
+    name: String,
+}
+
+impl VolumeHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns path to the `path` inside this volume.
+    pub fn path(&self, path: impl Into<PathBuf>) -> PrefixedPath {
+        PrefixedPath {
+            prefix: PathPrefix::Volume(self.name.clone()),
+            path: path.into(),
+        }
+    }
+}
+
+/// Handle of a sandbox.
+#[derive(Debug, Clone)]
+pub struct SandboxHandle {
+    builder: u64,
+    name: String,
+    resource: usize,
+}
+
+impl SandboxHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Handle of a command step.
+#[derive(Debug, Clone, Copy)]
+pub struct StepHandle {
+    builder: u64,
+    command: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ResourceKind {
+    Pipe,
+    Other,
+}
+
+/// Step creating a file, pipe, volume or sandbox.
+struct Resource {
+    kind: ResourceKind,
+    action: Action,
+}
+
+struct CommandNode {
+    command: Command,
+    /// Resources used by the command
+    uses: Vec<usize>,
+    /// Commands which must be finished before this command starts
+    after: Vec<usize>,
+    condition: Option<Condition>,
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    Succeeded(usize),
+    NoFailures,
+}
+
+/// Builds `InvokeRequest`. See module docs for details.
+pub struct RequestBuilder {
+    builder: u64,
+    id: uuid::Uuid,
+    budget: Option<Budget>,
+    inputs: Vec<Input>,
+    outputs: Vec<OutputRequest>,
+    resources: Vec<Resource>,
+    commands: Vec<CommandNode>,
+    /// Used to generate unique names
+    next_name: usize,
+}
+
+impl RequestBuilder {
+    pub fn new(id: uuid::Uuid) -> Self {
+        RequestBuilder {
+            builder: NEXT_BUILDER_ID.fetch_add(1, Ordering::Relaxed),
+            id,
+            budget: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            resources: Vec::new(),
+            commands: Vec::new(),
+            next_name: 0,
+        }
+    }
+
+    fn check(&self, builder: u64) {
+        assert_eq!(
+            builder, self.builder,
+            "handle was created by another RequestBuilder"
+        );
+    }
+
+    fn make_name(&mut self, kind: &str) -> String {
+        let name = format!("{}-{}", kind, self.next_name);
+        self.next_name += 1;
+        name
+    }
+
+    fn add_resource(&mut self, kind: ResourceKind, action: Action) -> usize {
+        self.resources.push(Resource { kind, action });
+        self.resources.len() - 1
+    }
+
+    fn add_file(&mut self, id: FileId, kind: ResourceKind, action: Action) -> FileHandle {
+        let resource = self.add_resource(kind, action);
+        FileHandle {
+            builder: self.builder,
+            id,
+            resource: Some(resource),
+        }
+    }
+
+    /// Sets request budget.
+    pub fn budget(&mut self, budget: Budget) -> &mut Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    fn add_input(&mut self, source: InputSource) -> FileHandle {
+        let id = FileId(self.make_name("input"));
+        self.inputs.push(Input {
+            file_id: id.clone(),
+            source,
+            ext: Extensions::default(),
+        });
+        FileHandle {
+            builder: self.builder,
+            id,
+            resource: None,
+        }
+    }
+
+    /// Adds input with given contents.
+    pub fn input_string(&mut self, data: impl Into<String>) -> FileHandle {
+        self.add_input(InputSource::InlineString { data: data.into() })
+    }
+
+    /// Adds input with given base64-encoded contents.
+    pub fn input_base64(&mut self, data: impl Into<String>) -> FileHandle {
+        self.add_input(InputSource::InlineBase64 { data: data.into() })
+    }
+
+    /// Adds input, read from the invoker's filesystem.
+    pub fn input_local_file(&mut self, path: impl Into<PathBuf>) -> FileHandle {
+        self.add_input(InputSource::LocalFile { path: path.into() })
+    }
+
+    /// Creates new empty file, which is both readable and writeable.
+    pub fn create_file(&mut self) -> FileHandle {
+        let id = FileId(self.make_name("file"));
+        let action = Action::CreateFile {
+            id: id.clone(),
+            readable: true,
+            writeable: true,
+        };
+        self.add_file(id, ResourceKind::Other, action)
+    }
+
+    /// Opens existing file. It is opened right before the first command
+    /// using it is started.
+    pub fn open_file(&mut self, path: PrefixedPath) -> FileHandle {
+        let id = FileId(self.make_name("file"));
+        let action = Action::OpenFile {
+            path,
+            id: id.clone(),
+        };
+        self.add_file(id, ResourceKind::Other, action)
+    }
+
+    /// Creates read-only empty file.
+    pub fn null_file(&mut self) -> FileHandle {
+        let id = FileId(self.make_name("null"));
+        let action = Action::OpenNullFile { id: id.clone() };
+        self.add_file(id, ResourceKind::Other, action)
+    }
+
+    /// Creates write-only file, used for command output nobody reads.
+    fn discard_file(&mut self) -> FileHandle {
+        let id = FileId(self.make_name("discard"));
+        let action = Action::CreateFile {
+            id: id.clone(),
+            readable: false,
+            writeable: true,
+        };
+        self.add_file(id, ResourceKind::Other, action)
+    }
+
+    /// Creates new pipe.
+    /// All commands using the pipe will be placed in the same stage.
+    pub fn pipe(&mut self) -> Pipe {
+        let name = self.make_name("pipe");
+        let read = FileId(format!("{}-read", name));
+        let write = FileId(format!("{}-write", name));
+        let resource = self.add_resource(
+            ResourceKind::Pipe,
+            Action::CreatePipe {
+                read: read.clone(),
+                write: write.clone(),
+            },
+        );
+        let handle = |id| FileHandle {
+            builder: self.builder,
+            id,
+            resource: Some(resource),
+        };
+        Pipe {
+            read: handle(read),
+            write: handle(write),
+        }
+    }
+
+    /// Creates new volume with optional size limit in bytes.
+    pub fn volume(&mut self, limit: Option<u64>) -> VolumeHandle {
+        let name = self.make_name("volume");
+        // Volumes are not tracked as used by commands, so they are created
+        // in the first stage, before sandboxes and files referring to them.
+        self.add_resource(
+            ResourceKind::Other,
+            Action::CreateVolume(VolumeSettings {
+                name: name.clone(),
+                limit,
+                ext: Extensions::default(),
+            }),
+        );
+        VolumeHandle {
+            builder: self.builder,
+            name,
+        }
+    }
+
+    /// Starts building new sandbox.
+    pub fn sandbox(
+        &mut self,
+        limits: Limits,
+        base_image: impl Into<PathBuf>,
+    ) -> SandboxBuilder<'_> {
+        let name = self.make_name("sandbox");
+        SandboxBuilder {
+            builder: self,
+            settings: SandboxSettings {
+                limits,
+                name,
+                base_image: base_image.into(),
+                expose: Vec::new(),
+                ext: Extensions::default(),
+            },
+        }
+    }
+
+    /// Starts building new command.
+    /// Unless configured otherwise, stdin of the command is redirected
+    /// to null file, stdout and stderr are written to files which are
+    /// never read, and working directory is `/`.
+    pub fn command<S: Into<String>>(
+        &mut self,
+        sandbox: &SandboxHandle,
+        argv: impl IntoIterator<Item = S>,
+    ) -> CommandBuilder<'_> {
+        self.check(sandbox.builder);
+        CommandBuilder {
+            node: CommandNode {
+                command: Command {
+                    sandbox_name: sandbox.name.clone(),
+                    argv: argv.into_iter().map(Into::into).collect(),
+                    env: Vec::new(),
+                    cwd: "/".to_string(),
+                    stdio: Stdio {
+                        stdin: FileId(String::new()),
+                        stdout: FileId(String::new()),
+                        stderr: FileId(String::new()),
+                        ext: Extensions::default(),
+                    },
+                    ext: Extensions::default(),
+                },
+                uses: vec![sandbox.resource],
+                after: Vec::new(),
+                condition: None,
+            },
+            stdio: [None, None, None],
+            builder: self,
+        }
+    }
+
+    fn add_output(&mut self, name: String, target: OutputRequestTarget) {
+        self.outputs.push(OutputRequest {
+            name,
+            target,
+            ext: Extensions::default(),
+        });
+    }
+
+    /// Requests contents of the file as an output.
+    pub fn output_file(&mut self, name: impl Into<String>, file: &FileHandle) -> &mut Self {
+        self.check(file.builder);
+        self.add_output(name.into(), OutputRequestTarget::File(file.id.clone()));
+        self
+    }
+
+    /// Requests contents of the file at `path` as an output.
+    pub fn output_path(&mut self, name: impl Into<String>, path: PrefixedPath) -> &mut Self {
+        self.add_output(name.into(), OutputRequestTarget::Path(path));
+        self
+    }
+
+    /// Assigns stages to commands. Returns error if commands sharing
+    /// a pipe are ordered one after another.
+    fn command_stages(&self) -> Result<(Vec<u32>, Vec<u32>), ApiError> {
+        let mut stages = vec![0; self.commands.len()];
+        let mut resource_stages = vec![0; self.resources.len()];
+        loop {
+            let mut changed = false;
+            for (i, node) in self.commands.iter().enumerate() {
+                let pipes = node
+                    .uses
+                    .iter()
+                    .copied()
+                    .filter(|&r| self.resources[r].kind == ResourceKind::Pipe);
+                let stage = node
+                    .after
+                    .iter()
+                    .map(|&dep| stages[dep] + 1)
+                    .chain(pipes.clone().map(|r| resource_stages[r]))
+                    .fold(stages[i], u32::max);
+                if stage as usize > self.commands.len() {
+                    return Err(ApiError::new(
+                        ErrorCode::DependencyCycle,
+                        format!(
+                            "Command {:?} shares a pipe with a command it is ordered after",
+                            node.command.argv
+                        ),
+                    ));
+                }
+                changed |= stages[i] != stage;
+                stages[i] = stage;
+                for r in pipes {
+                    changed |= resource_stages[r] != stage;
+                    resource_stages[r] = stage;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        // Other resources are created in the earliest stage where they are used.
+        for (i, resource) in self.resources.iter().enumerate() {
+            if resource.kind == ResourceKind::Pipe {
+                continue;
+            }
+            resource_stages[i] = self
+                .commands
+                .iter()
+                .zip(&stages)
+                .filter(|(node, _)| node.uses.contains(&i))
+                .map(|(_, &stage)| stage)
+                .min()
+                .unwrap_or(0);
+        }
+        Ok((stages, resource_stages))
+    }
+
+    /// Returns step id of the command, referenced by conditions.
+    fn step_id(command: usize) -> String {
+        format!("command-{}", command)
+    }
+
+    /// Builds the request.
+    pub fn build(self) -> Result<InvokeRequest, ApiError> {
+        let (stages, resource_stages) = self.command_stages()?;
+        let referenced = self
+            .commands
+            .iter()
+            .filter_map(|node| match node.condition {
+                Some(Condition::Succeeded(dep)) => Some(dep),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut steps = Vec::with_capacity(self.resources.len() + self.commands.len());
+        for (resource, &stage) in self.resources.into_iter().zip(&resource_stages) {
+            steps.push(Step {
+                stage,
+                action: resource.action,
+                id: None,
+                depends_on: Vec::new(),
+                condition: None,
+                ext: Extensions::default(),
+            });
+        }
+        for (i, (node, &stage)) in self.commands.into_iter().zip(&stages).enumerate() {
+            let condition = node.condition.map(|cond| match cond {
+                Condition::Succeeded(dep) => StepCondition::StepSucceeded(Self::step_id(dep)),
+                Condition::NoFailures => StepCondition::NoFailures,
+            });
+            steps.push(Step {
+                stage,
+                action: Action::ExecuteCommand(node.command),
+                id: referenced.contains(&i).then(|| Self::step_id(i)),
+                depends_on: Vec::new(),
+                condition,
+                ext: Extensions::default(),
+            });
+        }
+        // sort is stable, so resources are still created before commands
+        steps.sort_by_key(|step| step.stage);
+
+        Ok(InvokeRequest {
+            steps,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            id: self.id,
+            budget: self.budget,
+            ext: Extensions::default(),
+        })
+    }
+}
+
+/// Builds a sandbox. Created by `RequestBuilder::sandbox`.
+pub struct SandboxBuilder<'a> {
+    builder: &'a mut RequestBuilder,
+    settings: SandboxSettings,
+}
+
+impl<'a> SandboxBuilder<'a> {
+    /// Exposes `host_path` to the sandbox at `sandbox_path`.
+    pub fn expose(
+        mut self,
+        host_path: PrefixedPath,
+        sandbox_path: impl Into<PathBuf>,
+        mode: SharedDirectoryMode,
+    ) -> Self {
+        self.settings.expose.push(SharedDir {
+            host_path,
+            sandbox_path: sandbox_path.into(),
+            mode,
+            create: false,
+            ext: Extensions::default(),
+        });
+        self
+    }
+
+    /// Exposes the whole volume to the sandbox at `sandbox_path`.
+    pub fn expose_volume(
+        self,
+        volume: &VolumeHandle,
+        sandbox_path: impl Into<PathBuf>,
+        mode: SharedDirectoryMode,
+    ) -> Self {
+        self.builder.check(volume.builder);
+        self.expose(volume.path(""), sandbox_path, mode)
+    }
+
+    /// Adds the sandbox to the request.
+    pub fn add(self) -> SandboxHandle {
+        let name = self.settings.name.clone();
+        let resource = self
+            .builder
+            .add_resource(ResourceKind::Other, Action::CreateSandbox(self.settings));
+        SandboxHandle {
+            builder: self.builder.builder,
+            name,
+            resource,
+        }
+    }
+}
+
+/// Builds a command. Created by `RequestBuilder::command`.
+pub struct CommandBuilder<'a> {
+    builder: &'a mut RequestBuilder,
+    node: CommandNode,
+    /// stdin, stdout and stderr
+    stdio: [Option<FileHandle>; 3],
+}
+
+impl<'a> CommandBuilder<'a> {
+    fn use_file(&mut self, file: &FileHandle) {
+        self.builder.check(file.builder);
+        if let Some(resource) = file.resource {
+            self.node.uses.push(resource);
+        }
+    }
+
+    pub fn stdin(mut self, file: &FileHandle) -> Self {
+        self.stdio[0] = Some(file.clone());
+        self
+    }
+
+    pub fn stdout(mut self, file: &FileHandle) -> Self {
+        self.stdio[1] = Some(file.clone());
+        self
+    }
+
+    pub fn stderr(mut self, file: &FileHandle) -> Self {
+        self.stdio[2] = Some(file.clone());
+        self
+    }
+
+    /// Sets working directory (absolute path inside the sandbox).
+    pub fn cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.node.command.cwd = cwd.into();
+        self
+    }
+
+    /// Sets environment variable.
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.node.command.env.push(EnvironmentVariable {
+            name: name.into(),
+            value: EnvVarValue::Plain(value.into()),
+            ext: Extensions::default(),
+        });
+        self
+    }
+
+    /// Passes file handle number as environment variable.
+    pub fn env_file(mut self, name: impl Into<String>, file: &FileHandle) -> Self {
+        self.use_file(file);
+        self.node.command.env.push(EnvironmentVariable {
+            name: name.into(),
+            value: EnvVarValue::File(file.id.clone()),
+            ext: Extensions::default(),
+        });
+        self
+    }
+
+    /// Command will be started only after `step` is finished.
+    pub fn after(mut self, step: &StepHandle) -> Self {
+        self.builder.check(step.builder);
+        self.node.after.push(step.command);
+        self
+    }
+
+    /// Command will be started after `step` is finished, and only if it
+    /// succeeded. Otherwise, command is skipped.
+    pub fn only_if_succeeded(mut self, step: &StepHandle) -> Self {
+        self = self.after(step);
+        self.node.condition = Some(Condition::Succeeded(step.command));
+        self
+    }
+
+    /// Command will be skipped if any of the commands it is ordered after
+    /// (directly or transitively) has failed.
+    pub fn only_if_no_failures(mut self) -> Self {
+        self.node.condition = Some(Condition::NoFailures);
+        self
+    }
+
+    /// Adds the command to the request.
+    pub fn add(mut self) -> StepHandle {
+        let stdio = std::mem::take(&mut self.stdio);
+        let mut ids = Vec::with_capacity(3);
+        for (i, file) in Vec::from(stdio).into_iter().enumerate() {
+            let file = match file {
+                Some(file) => file,
+                None if i == 0 => self.builder.null_file(),
+                None => self.builder.discard_file(),
+            };
+            self.use_file(&file);
+            ids.push(file.id);
+        }
+        let mut ids = ids.into_iter();
+        let stdio = &mut self.node.command.stdio;
+        stdio.stdin = ids.next().unwrap();
+        stdio.stdout = ids.next().unwrap();
+        stdio.stderr = ids.next().unwrap();
+        self.builder.commands.push(self.node);
+        StepHandle {
+            builder: self.builder.builder,
+            command: self.builder.commands.len() - 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stages() {
+        let mut b = RequestBuilder::new(uuid::Uuid::nil());
+        let volume = b.volume(None);
+        let sandbox = b
+            .sandbox(Limits::new(1 << 30, 1000), "/")
+            .expose_volume(&volume, "/out", SharedDirectoryMode::ReadWrite)
+            .add();
+        let compile = b.command(&sandbox, vec!["gcc"]).add();
+        let pipe = b.pipe();
+        let solution = b
+            .command(&sandbox, vec!["./a.out"])
+            .stdout(&pipe.write)
+            .only_if_succeeded(&compile)
+            .add();
+        let answer = b.open_file(volume.path("answer.txt"));
+        b.command(&sandbox, vec!["interactor"])
+            .stdin(&pipe.read)
+            .env_file("ANSWER", &answer)
+            .add();
+        b.command(&sandbox, vec!["checker"])
+            .only_if_no_failures()
+            .after(&solution)
+            .add();
+        let req = b.build().unwrap();
+
+        assert!(crate::validate::validate(&req).is_empty());
+        let stage_of = |argv0: &str| {
+            req.steps
+                .iter()
+                .find(|step| match &step.action {
+                    Action::ExecuteCommand(cmd) => cmd.argv[0] == argv0,
+                    _ => false,
+                })
+                .unwrap()
+                .stage
+        };
+        assert_eq!(stage_of("gcc"), 0);
+        assert_eq!(stage_of("./a.out"), 1);
+        assert_eq!(stage_of("interactor"), 1);
+        assert_eq!(stage_of("checker"), 2);
+        let open_stage = req
+            .steps
+            .iter()
+            .find(|step| matches!(step.action, Action::OpenFile { .. }))
+            .unwrap()
+            .stage;
+        assert_eq!(open_stage, 1);
+    }
+
+    #[test]
+    fn test_pipe_between_ordered_commands() {
+        let mut b = RequestBuilder::new(uuid::Uuid::nil());
+        let sandbox = b.sandbox(Limits::new(1 << 30, 1000), "/").add();
+        let pipe = b.pipe();
+        let first = b.command(&sandbox, vec!["a"]).stdout(&pipe.write).add();
+        b.command(&sandbox, vec!["b"])
+            .stdin(&pipe.read)
+            .after(&first)
+            .add();
+        assert!(b.build().is_err());
+    }
+}
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 
 /// Sent as POST payload after sandbox creation
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct AttachRequest {
     /// Raw backend-specific details
@@ -0,0 +1,107 @@
+//! Machine-readable errors
+//! Errors caused by incorrect requests have stable codes, so that clients
+//! can distinguish them from invoker or shim faults.
+//! ## Endpoints
+//! `GET /errors/{id}` returns `ErrorReport` for the error with given id
+//! (e.g. `InvokeError::error_id` or `CommandResult::spawn_error`).
+//! Invoker only keeps limited number of recent errors, so this endpoint
+//! returns 404 for old errors.
+
+use crate::validate::Problem;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Error code. New codes can be added in future versions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    /// Request body is not a valid `InvokeRequest`
+    InvalidRequest,
+    /// Request contains extensions which were not consumed by the shim
+    UnresolvedExtensions,
+    /// Extensions are malformed or reference unknown items
+    InvalidExtensions,
+    /// Several steps have the same id
+    DuplicateStepId,
+    /// Step refers to a step id which does not exist
+    UnknownStepId,
+    /// Step dependencies contain a cycle
+    DependencyCycle,
+    /// Action or command refers to a file which does not exist
+    UnknownFileId,
+    /// Action creates a file with id that is already used
+    DuplicateFileId,
+    /// File can not be used this way, e.g. write-only file is used as stdin
+    InvalidFileMode,
+    /// Command refers to a sandbox which does not exist
+    UnknownSandbox,
+    /// Sandbox with the same name was already created
+    DuplicateSandbox,
+    /// Path refers to a volume which does not exist
+    UnknownVolume,
+    /// Volume with the same name was already created
+    DuplicateVolume,
+    /// Path must be relative, but it is absolute
+    PathNotRelative,
+    /// Action parameters are invalid, e.g. command argv is empty
+    InvalidAction,
+    /// Request with the same id is already being executed
+    DuplicateRequestId,
+    /// Internal error of the invoker or shim
+    Internal,
+}
+
+impl ErrorCode {
+    /// Returns true if error is caused by the incorrect request.
+    pub fn is_user_error(self) -> bool {
+        self != ErrorCode::Internal
+    }
+}
+
+/// Error with the machine-readable code
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    /// Human-readable description
+    pub message: String,
+    /// All problems found in the request, if error was returned
+    /// by the request validation.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<Problem>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ApiError {
+            code,
+            message: message.into(),
+            problems: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Describes an error which happened in the invoker
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ErrorReport {
+    pub id: uuid::Uuid,
+    /// Error message, including all causes
+    pub message: String,
+    /// When the error happened, in microseconds since Unix epoch
+    pub timestamp: u64,
+}
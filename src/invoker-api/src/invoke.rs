@@ -2,7 +2,16 @@
 //! Requests invoker to execute commands, specified in
 //! `steps` field in request.
 //! ## Endpoints
-//! `POST /exec`
+//! `POST /exec` returns `InvokeResponse`. If invoker fails to execute the
+//! request or the shim rejects it, invoker returns `InvokeError` in the
+//! body, with status 400 if the error is caused by incorrect request (see
+//! `error::ErrorCode`) and 500 otherwise.
+//!
+//! `POST /cancel/{id}` cancels in-flight request with the given id
+//! (submitted either via `/exec` or via jobs API).
+//!
+//! `GET /openapi.json` and `GET /schema.json` return OpenAPI document and
+//! JSON Schema of the API (see `schema` module).
 //! ## Execution order
 //! Each step has assigned `stage`.
 //! Steps with equal stage will be executed in the same time.
@@ -11,12 +20,34 @@
 //! steps creating new IPC stuff are executed first and then commands are run.
 //! Step will not be executed until all steps with less `stage`
 //! will be finished.
+//!
+//! Additionally, step can be assigned an `id` and list other steps in
+//! `dependsOn`. Such step will not be executed until all listed steps are
+//! finished. This allows expressing arbitrary dependency graph inside
+//! a single stage. Requests with cyclic dependencies are rejected.
+//! ## Validation
+//! Before execution, request is checked using `validate` module, and all
+//! found problems are reported at once.
+//! ## Conditions
+//! Step can specify a `condition`. If it does not hold, step is skipped.
+//! For example, this can be used to avoid running solution if its
+//! compilation has failed.
 //! ## Data
 //! `InvokeRequest` can specify input data items, that can be further used
 //! as stdin for executed commands (input data item can be used several times).
 //! ## DataRequest
 //! `InvokeRequest` can specify output data requests, which will be populated
 //! from some files, created by `CreateFile` action.
+//! ## Cancellation
+//! When request is cancelled (explicitly or because client has closed
+//! the connection), invoker kills all processes in request's sandboxes,
+//! unmounts volumes and stops executing new steps. Response contains
+//! results of the steps which were finished and has `cancelled` set.
+//! Outputs are not collected for cancelled requests.
+//! ## Budget
+//! Request can specify `budget`, limiting total wall-clock time and CPU time
+//! of the request. When budget is exhausted, request is stopped the same way
+//! as on cancellation, but `budgetExceeded` is set instead of `cancelled`.
 //! ## Extensions
 //! You can specify extensions at different levels of the InvokeRequest obkect.
 //! This extensions must be consumed and stripped by the shim.
@@ -26,6 +57,7 @@ use std::{fmt, path::PathBuf};
 
 /// Location exposed to the sandbox
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum PathPrefix {
@@ -39,6 +71,7 @@ pub enum PathPrefix {
 
 /// A path with the prefix
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct PrefixedPath {
@@ -49,7 +82,18 @@ pub struct PrefixedPath {
     pub path: PathBuf,
 }
 
+impl PrefixedPath {
+    /// Path on the host filesystem.
+    pub fn host(path: impl Into<PathBuf>) -> Self {
+        PrefixedPath {
+            prefix: PathPrefix::Host,
+            path: path.into(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct InvokeRequest {
@@ -62,11 +106,34 @@ pub struct InvokeRequest {
     /// Request identifier.
     /// Will be returned as-is in response.
     pub id: uuid::Uuid,
+    /// Limits for the request as a whole.
+    #[serde(default)]
+    pub budget: Option<Budget>,
     #[serde(default)]
     pub ext: Extensions,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Resource budget of the whole request.
+/// When it is exhausted, invoker stops executing steps (killing running
+/// commands if deadline is reached) and returns results of the finished
+/// steps, with `budgetExceeded` set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct Budget {
+    /// Maximum wall-clock time of the request execution in milliseconds.
+    #[serde(default)]
+    pub wall_time: Option<u64>,
+    /// Maximum total CPU time of all commands in milliseconds.
+    /// It is checked when commands finish, so the actual usage
+    /// can exceed it by limits of the last executed commands.
+    #[serde(default)]
+    pub cpu_time: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct InvokeResponse {
@@ -74,11 +141,69 @@ pub struct InvokeResponse {
     pub id: uuid::Uuid,
     /// Outputs for all OutputRequest (the same order as in request).
     pub outputs: Vec<Output>,
-    /// Results of all actions
-    pub actions: Vec<ActionResult>,
+    /// Results of all executed steps, in completion order.
+    ///
+    /// Breaking change in 0.2: this used to be a list of `ActionResult`s,
+    /// which is now available as `StepResult::result`.
+    pub actions: Vec<StepResult>,
+    /// True if request was cancelled before all steps were executed.
+    #[serde(default)]
+    pub cancelled: bool,
+    /// True if request budget was exhausted before all steps were executed.
+    #[serde(default)]
+    pub budget_exceeded: bool,
+}
+
+/// Returned when invoker failed to execute the request.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct InvokeError {
+    /// Error identifier, which can be used to find the error in invoker logs.
+    /// Also returned in the `Error-UUID` header.
+    pub error_id: uuid::Uuid,
+    /// Error kind
+    pub code: crate::error::ErrorCode,
+    /// Problems found in the request (see `validate` module)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<crate::validate::Problem>,
+    /// Error message followed by its causes, from outermost to innermost.
+    pub error_chain: Vec<String>,
+    /// Index of the step which caused the error, if the error is related
+    /// to a particular step.
+    #[serde(default)]
+    pub step_index: Option<usize>,
+    /// Results of steps which were finished before the error
+    /// and outputs which were collected.
+    /// Not set if no step was finished before the error (e.g. if the
+    /// request is invalid).
+    #[serde(default)]
+    pub partial_response: Option<InvokeResponse>,
+}
+
+/// Describes the outcome of a single step.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct StepResult {
+    /// Index of the step in `InvokeRequest::steps`.
+    pub step_index: usize,
+    /// Step id, if it was specified in the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step_id: Option<String>,
+    /// Action result
+    pub result: ActionResult,
+    /// Time when step was started, in microseconds since Unix epoch.
+    pub started_at: u64,
+    /// Time when step was finished, in microseconds since Unix epoch.
+    pub finished_at: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct OutputRequest {
@@ -91,6 +216,7 @@ pub struct OutputRequest {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum OutputRequestTarget {
@@ -101,6 +227,7 @@ pub enum OutputRequestTarget {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Input {
@@ -113,6 +240,7 @@ pub struct Input {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum InputSource {
@@ -124,7 +252,8 @@ pub enum InputSource {
     InlineBase64 { data: String },
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Output {
@@ -134,7 +263,8 @@ pub struct Output {
     pub data: OutputData,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum OutputData {
@@ -147,17 +277,50 @@ pub enum OutputData {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Step {
     pub stage: u32,
     pub action: Action,
+    /// Step identifier, which can be referenced in `depends_on`.
+    /// Must be unique within the request.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Identifiers of steps that must be finished before this step starts.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// If set, step will only be executed when condition holds.
+    /// Otherwise, it will be skipped and `ActionResult::Skipped` will be
+    /// reported.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<StepCondition>,
     #[serde(default)]
     pub ext: Extensions,
 }
 
+/// Condition, checked before the step is executed.
+/// Step is considered failed if it was skipped, or if it is a command that
+/// failed to start or exited with non-zero code.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub enum StepCondition {
+    /// Step with given id was executed and did not fail.
+    /// This implies dependency on that step.
+    StepSucceeded(String),
+    /// None of the steps this step depends on (directly or transitively,
+    /// including steps from earlier stages) has failed.
+    NoFailures,
+}
+
 /// Newtype identifier of file-like object, e.g. real file or pipe.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(transparent)]
 pub struct FileId(pub String);
 
@@ -169,6 +332,7 @@ impl fmt::Display for FileId {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Command {
@@ -183,6 +347,7 @@ pub struct Command {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct EnvironmentVariable {
@@ -194,6 +359,7 @@ pub struct EnvironmentVariable {
 
 /// Allowed access to shared directory
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum SharedDirectoryMode {
@@ -205,6 +371,7 @@ pub enum SharedDirectoryMode {
 
 /// Piece of filesystem that should be exposed to sandbox.
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct SharedDir {
@@ -223,6 +390,7 @@ pub struct SharedDir {
 
 /// Value of the environment variable
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum EnvVarValue {
@@ -234,6 +402,7 @@ pub enum EnvVarValue {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Stdio {
@@ -246,6 +415,7 @@ pub struct Stdio {
 
 /// Describer limits that should be applied to a sandbox.
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Limits {
@@ -253,15 +423,58 @@ pub struct Limits {
     pub memory: u64,
     /// Time limit in milliseconds
     pub time: u64,
+    /// Real (wall-clock) time limit in milliseconds.
+    /// If not set, it is derived from `time` using invoker-wide multiplier.
+    #[serde(default)]
+    pub wall_time: Option<u64>,
+    /// Idle time limit in milliseconds.
+    /// If CPU time usage of the sandbox does not grow for this time while
+    /// a command is running (e.g. process is blocked reading from a pipe),
+    /// all processes in the sandbox are killed.
+    #[serde(default)]
+    pub idle_time: Option<u64>,
     /// Process count limit.
     #[serde(default)]
     pub process_count: Option<u64>,
+    /// Maximum size of a file that process can write, in bytes.
+    #[serde(default)]
+    pub file_size: Option<u64>,
+    /// Maximum number of open file descriptors per process.
+    /// Note that too small values can prevent command from starting.
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    /// Stack size limit in bytes.
+    #[serde(default)]
+    pub stack_size: Option<u64>,
+    /// Maximum size of a core dump in bytes.
+    #[serde(default)]
+    pub core_dump_size: Option<u64>,
     #[serde(default)]
     pub ext: Extensions,
 }
 
+impl Limits {
+    /// Creates limits with given memory limit (in bytes) and time limit
+    /// (in milliseconds). Other limits are not set.
+    pub fn new(memory: u64, time: u64) -> Self {
+        Limits {
+            memory,
+            time,
+            wall_time: None,
+            idle_time: None,
+            process_count: None,
+            file_size: None,
+            open_files: None,
+            stack_size: None,
+            core_dump_size: None,
+            ext: Extensions::default(),
+        }
+    }
+}
+
 /// Sandbox settings
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct SandboxSettings {
@@ -283,6 +496,7 @@ pub struct SandboxSettings {
 /// Volume is a directory on invoker filesystem, which can be later
 /// exposed to sandboxes. Location of the volume is chosen by the invoker.
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct VolumeSettings {
@@ -295,7 +509,8 @@ pub struct VolumeSettings {
     pub ext: Extensions,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum ActionResult {
@@ -306,33 +521,127 @@ pub enum ActionResult {
     ExecuteCommand(CommandResult),
     CreateSandbox,
     CreateVolume,
+    /// Step was not executed because its condition did not hold.
+    Skipped,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct CommandResult {
     /// If this field is set, command failed to start.
+    /// Contains error id, which can be used to get error details
+    /// (see `error` module).
     /// Other fields will have unspecified values.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub spawn_error: Option<uuid::Uuid>,
     /// Process exit code
     pub exit_code: i64,
-    /// CPU time usage in nanoseconds (but precision will be likely coarser).
+    /// CPU time used while this command was running, in nanoseconds
+    /// (but precision will be likely coarser).
+    /// If several commands run in one sandbox simultaneously, their usage
+    /// can not be separated.
     pub cpu_time: Option<u64>,
-    /// Memory usage in bytes (but precision will be likely coarser).
+    /// Peak memory usage of the sandbox while this command was running,
+    /// in bytes. Memory is accounted per sandbox cgroup, so commands running
+    /// in one sandbox simultaneously share this value.
+    /// Measuring the peak of a single command requires cgroup v2 and
+    /// Linux 6.12 or newer. Otherwise this is the peak usage of the sandbox
+    /// since it was created, which is larger than the usage of this command
+    /// if an earlier command in the sandbox used more memory.
+    /// Not set if memory usage can not be measured at all.
     pub memory: Option<u64>,
+    /// Wall-clock time between command start and exit, in nanoseconds.
+    #[serde(default)]
+    pub wall_time: Option<u64>,
+    /// Total resource usage of the sandbox, i.e. all commands executed in
+    /// this sandbox so far, measured when this command exited.
+    #[serde(default)]
+    pub sandbox_usage: Option<ResourceUsage>,
+    /// Describes how the command terminated.
+    /// Not set if the command failed to start.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub termination: Option<Termination>,
+}
+
+/// Resource usage of the sandbox
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ResourceUsage {
+    /// CPU time usage in nanoseconds
+    pub cpu_time: Option<u64>,
+    /// Peak memory usage of the sandbox since it was created, in bytes
+    pub memory: Option<u64>,
+}
+
+/// Main reason of the command termination.
+/// If several limits were exceeded, the first one in this list is reported.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub enum TerminationReason {
+    /// CPU time limit was exceeded.
+    CpuTimeLimitExceeded,
+    /// Real (wall-clock) time limit was exceeded.
+    RealTimeLimitExceeded,
+    /// Process did not consume CPU time for too long.
+    IdleTimeExceeded,
+    /// Memory limit was exceeded, and the OOM killer killed a process.
+    MemoryLimitExceeded,
+    /// Process count limit was reached, so some process failed to start.
+    /// Such process does not terminate itself, but commands usually fail
+    /// because of it.
+    ProcessLimitExceeded,
+    /// Process was killed by a signal (e.g. SIGSEGV).
+    Signaled,
+    /// Process exited on its own.
+    Exited,
+}
+
+/// Details about the command termination.
+/// Note that limits are applied to the whole sandbox, so flags can be
+/// set because of other commands running in the same sandbox at the
+/// same time.
+/// Memory and process count limit violations are detected using cgroup v2
+/// event counters, so they are never reported if the sandbox does not have
+/// its own cgroup (see `docs/rootless.md`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct Termination {
+    pub reason: TerminationReason,
+    /// Number of the signal which killed the process, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// Time limits apply to the whole sandbox. Only the command which was
+    /// running when the sandbox exceeded the limit reports it.
+    pub cpu_time_limit_exceeded: bool,
+    pub real_time_limit_exceeded: bool,
+    pub idle_time_limit_exceeded: bool,
+    pub memory_limit_exceeded: bool,
+    /// Some `fork` failed while this command was running, because process
+    /// count limit was reached.
+    #[serde(default)]
+    pub process_limit_exceeded: bool,
 }
 
 /// Single action of execution plan.
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub enum Action {
     /// Specifies that a pipe must be allocated.
     /// Use CreatePipe instead if you want to export
     /// written data as an output instead of sending
-    /// it to another program
+    /// it to another program.
+    /// Invoker closes its own copy of each half as soon as all commands
+    /// using it were spawned, so readers observe EOF when writers exit.
     CreatePipe {
         /// Will be associated with pipe's read half
         read: FileId,
@@ -369,6 +678,7 @@ pub enum Action {
 
 /// Arbitrary key-value data (i.e. json Object), consumed by the shim.
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(transparent)]
 pub struct Extensions(pub serde_json::Map<String, serde_json::Value>);
 
@@ -0,0 +1,59 @@
+//! # Jobs API
+//! Asynchronous alternative to `POST /exec`, useful when invocation takes
+//! longer than client or proxy timeouts.
+//! ## Endpoints
+//! `POST /jobs` accepts the same body as `POST /exec` and returns `JobCreated`.
+//! Job is identified by the `id` of the submitted request.
+//!
+//! `GET /jobs/{id}` returns `JobInfo`.
+//!
+//! `DELETE /jobs/{id}` cancels the job if it is still running and forgets it.
+//! To cancel the job while keeping partial results, use `POST /cancel/{id}`
+//! and then fetch the job.
+//! Clients should delete jobs after fetching results, otherwise invoker
+//! will only keep limited number of finished jobs.
+
+use crate::{
+    error::ApiError,
+    invoke::{InvokeError, InvokeResponse},
+};
+use serde::{Deserialize, Serialize};
+
+/// Returned when job was submitted
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct JobCreated {
+    /// Job identifier (equal to the request id)
+    pub id: uuid::Uuid,
+}
+
+/// Job state
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub enum JobStatus {
+    /// Job is being executed
+    Running,
+    /// Job completed successfully
+    Finished(InvokeResponse),
+    /// Request was rejected by the shim.
+    /// Contains error returned by the shim.
+    Rejected(ApiError),
+    /// Job failed because of internal error.
+    Failed(InvokeError),
+}
+
+/// Describes job
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct JobInfo {
+    /// Job identifier
+    pub id: uuid::Uuid,
+    /// Current job status
+    pub status: JobStatus,
+}
@@ -1,7 +1,13 @@
 //! Defines Invoker API.
 //! You can use invoker to securely execute untrusted programs.
 
+pub mod builder;
 pub mod debug;
+pub mod error;
 pub mod invoke;
+pub mod jobs;
+#[cfg(feature = "schemars")]
+pub mod schema;
 pub mod shim;
 pub mod shims;
+pub mod validate;
@@ -0,0 +1,219 @@
+//! JSON Schema and OpenAPI descriptions of the invoker API.
+//! Requires `schemars` feature.
+
+use crate::{
+    debug::AttachRequest,
+    error::{ApiError, ErrorReport},
+    invoke::{InvokeError, InvokeRequest, InvokeResponse},
+    jobs::{JobCreated, JobInfo},
+    shims::ShimResponse,
+};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use serde_json::{json, Value};
+
+fn generate(settings: SchemaSettings) -> SchemaGenerator {
+    let mut gen = settings.into_generator();
+    gen.subschema_for::<InvokeRequest>();
+    gen.subschema_for::<InvokeResponse>();
+    gen.subschema_for::<InvokeError>();
+    gen.subschema_for::<ApiError>();
+    gen.subschema_for::<ErrorReport>();
+    gen.subschema_for::<JobCreated>();
+    gen.subschema_for::<JobInfo>();
+    gen.subschema_for::<ShimResponse>();
+    gen.subschema_for::<AttachRequest>();
+    gen
+}
+
+/// Returns JSON Schema (draft 7), defining all API types.
+pub fn json_schema() -> Value {
+    let gen = generate(SchemaSettings::draft07());
+    json!({
+        "$schema": gen.settings().meta_schema,
+        "title": "Invoker API",
+        "definitions": gen.definitions(),
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn response(description: &str, schema_name: &str) -> Value {
+    json!({
+        "description": description,
+        "content": json_content(schema_ref(schema_name)),
+    })
+}
+
+fn empty_response(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn id_parameter() -> Value {
+    json!([{
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string", "format": "uuid" }
+    }])
+}
+
+/// Returns OpenAPI 3.0 document, describing invoker and shim endpoints.
+pub fn openapi() -> Value {
+    let gen = generate(SchemaSettings::openapi3());
+    let invoke_request_body = json!({
+        "required": true,
+        "content": json_content(schema_ref("InvokeRequest")),
+    });
+    let shutting_down = json!({
+        "description": "Invoker is shutting down and does not accept new requests",
+        "content": json_content(json!({
+            "type": "object",
+            "properties": {
+                "error": { "type": "string" },
+            }
+        })),
+    });
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Invoker API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/exec": {
+                "post": {
+                    "tags": ["invoker"],
+                    "summary": "Execute request and wait for completion",
+                    "requestBody": invoke_request_body,
+                    "responses": {
+                        "200": response("Request was executed", "InvokeResponse"),
+                        "400": response(
+                            "Request is invalid or was rejected by the shim",
+                            "InvokeError"
+                        ),
+                        "500": response("Internal error", "InvokeError"),
+                        "503": shutting_down,
+                    }
+                }
+            },
+            "/jobs": {
+                "post": {
+                    "tags": ["invoker"],
+                    "summary": "Start executing request in background",
+                    "requestBody": invoke_request_body,
+                    "responses": {
+                        "202": response("Job was created", "JobCreated"),
+                        "400": response("Request id is missing or invalid", "ApiError"),
+                        "409": response("Job with this id already exists", "ApiError"),
+                        "503": shutting_down,
+                    }
+                }
+            },
+            "/jobs/{id}": {
+                "parameters": id_parameter(),
+                "get": {
+                    "tags": ["invoker"],
+                    "summary": "Get job status",
+                    "responses": {
+                        "200": response("Job status", "JobInfo"),
+                        "404": empty_response("Job not found"),
+                    }
+                },
+                "delete": {
+                    "tags": ["invoker"],
+                    "summary": "Cancel job if it is running and forget it",
+                    "responses": {
+                        "204": empty_response("Job was deleted"),
+                        "404": empty_response("Job not found"),
+                    }
+                }
+            },
+            "/cancel/{id}": {
+                "parameters": id_parameter(),
+                "post": {
+                    "tags": ["invoker"],
+                    "summary": "Cancel running request",
+                    "responses": {
+                        "202": empty_response("Cancellation was requested"),
+                        "404": empty_response("Request is not running"),
+                    }
+                }
+            },
+            "/errors/{id}": {
+                "parameters": id_parameter(),
+                "get": {
+                    "tags": ["invoker"],
+                    "summary": "Get error details",
+                    "responses": {
+                        "200": response("Error details", "ErrorReport"),
+                        "404": empty_response("Error not found or already forgotten"),
+                    }
+                }
+            },
+            "/ready": {
+                "get": {
+                    "tags": ["invoker", "shim"],
+                    "summary": "Readiness check",
+                    "responses": {
+                        "200": empty_response("Service is ready"),
+                        "503": empty_response("Invoker is shutting down"),
+                    }
+                }
+            },
+            "/on-request": {
+                "post": {
+                    "tags": ["shim"],
+                    "summary": "Preprocess request (implemented by the shim)",
+                    "requestBody": invoke_request_body,
+                    "responses": {
+                        "200": response("Request was accepted", "ShimResponse"),
+                        "400": response("Request was rejected", "ShimResponse"),
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": gen.definitions(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(r)) = map.get("$ref") {
+                    refs.push(r);
+                }
+                map.values().for_each(|v| collect_refs(v, refs));
+            }
+            Value::Array(items) => items.iter().for_each(|v| collect_refs(v, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn openapi_refs_are_resolved() {
+        let doc = openapi();
+        let mut refs = Vec::new();
+        collect_refs(&doc, &mut refs);
+        assert!(!refs.is_empty());
+        for r in refs {
+            let name = r.trim_start_matches("#/components/schemas/");
+            assert!(
+                doc["components"]["schemas"].get(name).is_some(),
+                "unresolved reference {}",
+                r
+            );
+        }
+    }
+}
@@ -8,6 +8,7 @@ pub const EXTRA_FILES_DIR_NAME: &str = "EXTRA_FILES";
 
 /// See RequestExtensions::extra_files
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct ExtraFile {
@@ -19,6 +20,7 @@ pub struct ExtraFile {
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct RequestExtensions {
@@ -34,6 +36,7 @@ pub struct RequestExtensions {
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct SandboxSettingsExtensions {
@@ -42,6 +45,7 @@ pub struct SandboxSettingsExtensions {
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct SharedDirExtensionSource {
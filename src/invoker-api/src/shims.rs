@@ -3,14 +3,15 @@
 use serde::{Deserialize, Serialize};
 
 /// Represents shim response
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum ShimResponse {
     /// Shim accepted the request and modified it
     Result(crate::invoke::InvokeRequest),
-    /// Shim rejected the request, and provided value
+    /// Shim rejected the request, and provided error
     /// that should be returned to the user
-    Error(serde_json::Value),
+    Error(crate::error::ApiError),
 }
 
 impl ShimResponse {
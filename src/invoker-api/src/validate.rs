@@ -0,0 +1,417 @@
+//! Static checks for `InvokeRequest`
+//! Finds mistakes which otherwise would only be detected in the middle
+//! of request execution, e.g. references to unknown files or sandboxes.
+//! Items provided via extensions are not checked, so requests can be
+//! validated both before and after shim processing.
+
+use crate::{
+    error::{ApiError, ErrorCode},
+    invoke::{
+        Action, Command, EnvVarValue, FileId, InvokeRequest, OutputRequestTarget, PathPrefix,
+        PrefixedPath, StepCondition,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Single problem found in the request
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct Problem {
+    pub code: ErrorCode,
+    /// Location of the problem, e.g. `steps[3].stdio.stdin`
+    pub location: String,
+    /// Human-readable description
+    pub message: String,
+}
+
+/// Where some named object is defined
+struct Definition {
+    location: String,
+    /// None for objects defined outside of steps (i.e. inputs)
+    stage: Option<u32>,
+    /// Set for pipe ends
+    is_pipe: bool,
+}
+
+#[derive(Default)]
+struct Validator {
+    problems: Vec<Problem>,
+    files: HashMap<FileId, Definition>,
+    sandboxes: HashMap<String, Definition>,
+    volumes: HashMap<String, Definition>,
+}
+
+impl Validator {
+    fn report(&mut self, code: ErrorCode, location: String, message: String) {
+        self.problems.push(Problem {
+            code,
+            location,
+            message,
+        });
+    }
+
+    fn define_file(&mut self, id: &FileId, location: String, stage: Option<u32>, is_pipe: bool) {
+        if let Some(prev) = self.files.get(id) {
+            let message = format!("file id {} is already defined at {}", id, prev.location);
+            self.report(ErrorCode::DuplicateFileId, location, message);
+            return;
+        }
+        let def = Definition {
+            location,
+            stage,
+            is_pipe,
+        };
+        self.files.insert(id.clone(), def);
+    }
+
+    /// Checks that file is defined before it is used in `stage`.
+    fn use_file(&mut self, id: &FileId, location: String, stage: Option<u32>) {
+        let def = match self.files.get(id) {
+            Some(d) => d,
+            None => {
+                let message = format!("unknown file id {}", id);
+                self.report(ErrorCode::UnknownFileId, location, message);
+                return;
+            }
+        };
+        let (def_stage, use_stage) = match (def.stage, stage) {
+            (Some(d), Some(u)) => (d, u),
+            _ => return,
+        };
+        if def.is_pipe && def_stage != use_stage {
+            let message = format!(
+                "pipe end {} is created in stage {}, but used in stage {}: \
+                 pipes can not be shared between stages",
+                id, def_stage, use_stage
+            );
+            self.report(ErrorCode::InvalidAction, location, message);
+        } else if def_stage > use_stage {
+            let message = format!(
+                "file {} is created in stage {}, after it is used in stage {}",
+                id, def_stage, use_stage
+            );
+            self.report(ErrorCode::UnknownFileId, location, message);
+        }
+    }
+
+    fn check_path(&mut self, path: &PrefixedPath, location: String, stage: Option<u32>) {
+        if !path.path.is_relative() {
+            let message = format!("path {} must be relative", path.path.display());
+            self.report(ErrorCode::PathNotRelative, location.clone(), message);
+        }
+        let name = match &path.prefix {
+            PathPrefix::Volume(name) => name,
+            // host paths are always available, and extensions
+            // are resolved by the shim
+            PathPrefix::Host | PathPrefix::Extension(_) => return,
+        };
+        match self.volumes.get(name) {
+            None => {
+                let message = format!("unknown volume {}", name);
+                self.report(ErrorCode::UnknownVolume, location, message);
+            }
+            Some(def) => {
+                if let (Some(def_stage), Some(use_stage)) = (def.stage, stage) {
+                    if def_stage > use_stage {
+                        let message = format!(
+                            "volume {} is created in stage {}, after it is used in stage {}",
+                            name, def_stage, use_stage
+                        );
+                        self.report(ErrorCode::UnknownVolume, location, message);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_command(&mut self, command: &Command, location: &str, stage: u32) {
+        match self.sandboxes.get(&command.sandbox_name) {
+            None => {
+                let message = format!("unknown sandbox {}", command.sandbox_name);
+                let location = format!("{}.sandboxName", location);
+                self.report(ErrorCode::UnknownSandbox, location, message);
+            }
+            Some(def) => {
+                if def.stage.is_some_and(|def_stage| def_stage > stage) {
+                    let message = format!(
+                        "sandbox {} is created in a later stage",
+                        command.sandbox_name
+                    );
+                    let location = format!("{}.sandboxName", location);
+                    self.report(ErrorCode::UnknownSandbox, location, message);
+                }
+            }
+        }
+        if command.argv.is_empty() {
+            let message = "argv must be non-empty".to_string();
+            self.report(
+                ErrorCode::InvalidAction,
+                format!("{}.argv", location),
+                message,
+            );
+        }
+        let stdio = &command.stdio;
+        self.use_file(
+            &stdio.stdin,
+            format!("{}.stdio.stdin", location),
+            Some(stage),
+        );
+        self.use_file(
+            &stdio.stdout,
+            format!("{}.stdio.stdout", location),
+            Some(stage),
+        );
+        self.use_file(
+            &stdio.stderr,
+            format!("{}.stdio.stderr", location),
+            Some(stage),
+        );
+        for (i, var) in command.env.iter().enumerate() {
+            if let EnvVarValue::File(id) = &var.value {
+                self.use_file(id, format!("{}.env[{}]", location, i), Some(stage));
+            }
+        }
+    }
+
+    /// Collects all defined objects
+    fn collect_definitions(&mut self, req: &InvokeRequest) {
+        for (i, input) in req.inputs.iter().enumerate() {
+            self.define_file(&input.file_id, format!("inputs[{}]", i), None, false);
+        }
+        for (i, step) in req.steps.iter().enumerate() {
+            let location = format!("steps[{}]", i);
+            let stage = Some(step.stage);
+            match &step.action {
+                Action::CreateFile { id, .. }
+                | Action::OpenFile { id, .. }
+                | Action::OpenNullFile { id } => self.define_file(id, location, stage, false),
+                Action::CreatePipe { read, write } => {
+                    self.define_file(read, format!("{}.read", location), stage, true);
+                    self.define_file(write, format!("{}.write", location), stage, true);
+                }
+                Action::CreateSandbox(settings) => {
+                    if let Some(prev) = self.sandboxes.get(&settings.name) {
+                        let message = format!(
+                            "sandbox {} is already created at {}",
+                            settings.name, prev.location
+                        );
+                        self.report(ErrorCode::DuplicateSandbox, location, message);
+                        continue;
+                    }
+                    let def = Definition {
+                        location,
+                        stage,
+                        is_pipe: false,
+                    };
+                    self.sandboxes.insert(settings.name.clone(), def);
+                }
+                Action::CreateVolume(settings) => {
+                    if let Some(prev) = self.volumes.get(&settings.name) {
+                        let message = format!(
+                            "volume {} is already created at {}",
+                            settings.name, prev.location
+                        );
+                        self.report(ErrorCode::DuplicateVolume, location, message);
+                        continue;
+                    }
+                    let def = Definition {
+                        location,
+                        stage,
+                        is_pipe: false,
+                    };
+                    self.volumes.insert(settings.name.clone(), def);
+                }
+                Action::ExecuteCommand(_) => (),
+            }
+        }
+    }
+
+    fn check_steps(&mut self, req: &InvokeRequest) {
+        let mut step_ids = HashMap::new();
+        for (i, step) in req.steps.iter().enumerate() {
+            if let Some(id) = &step.id {
+                if let Some(prev) = step_ids.insert(id.as_str(), i) {
+                    let message = format!("step id {} is already used by steps[{}]", id, prev);
+                    self.report(
+                        ErrorCode::DuplicateStepId,
+                        format!("steps[{}].id", i),
+                        message,
+                    );
+                }
+            }
+        }
+        for (i, step) in req.steps.iter().enumerate() {
+            let location = format!("steps[{}]", i);
+            for (j, dep) in step.depends_on.iter().enumerate() {
+                if !step_ids.contains_key(dep.as_str()) {
+                    let message = format!("unknown step id {}", dep);
+                    let location = format!("{}.dependsOn[{}]", location, j);
+                    self.report(ErrorCode::UnknownStepId, location, message);
+                }
+            }
+            if let Some(StepCondition::StepSucceeded(dep)) = &step.condition {
+                if !step_ids.contains_key(dep.as_str()) {
+                    let message = format!("unknown step id {}", dep);
+                    let location = format!("{}.condition", location);
+                    self.report(ErrorCode::UnknownStepId, location, message);
+                }
+            }
+            let stage = Some(step.stage);
+            match &step.action {
+                Action::CreateFile {
+                    readable: false,
+                    writeable: false,
+                    ..
+                } => {
+                    let message = "neither readable nor writeable flags are set".to_string();
+                    self.report(ErrorCode::InvalidAction, location, message);
+                }
+                Action::OpenFile { path, .. } => {
+                    self.check_path(path, format!("{}.path", location), stage);
+                }
+                Action::CreateSandbox(settings) => {
+                    for (j, dir) in settings.expose.iter().enumerate() {
+                        let location = format!("{}.expose[{}].hostPath", location, j);
+                        self.check_path(&dir.host_path, location, stage);
+                    }
+                }
+                Action::ExecuteCommand(command) => {
+                    self.check_command(command, &location, step.stage);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn check_outputs(&mut self, req: &InvokeRequest) {
+        let mut names = HashSet::new();
+        for (i, output) in req.outputs.iter().enumerate() {
+            let location = format!("outputs[{}]", i);
+            if !names.insert(output.name.as_str()) {
+                let message = format!("output name {} is used more than once", output.name);
+                self.report(
+                    ErrorCode::InvalidRequest,
+                    format!("{}.name", location),
+                    message,
+                );
+            }
+            // outputs are collected after all steps
+            match &output.target {
+                OutputRequestTarget::File(id) => {
+                    self.use_file(id, format!("{}.target", location), None)
+                }
+                OutputRequestTarget::Path(path) => {
+                    self.check_path(path, format!("{}.target", location), None)
+                }
+            }
+        }
+    }
+}
+
+/// Checks the request and returns all found problems.
+/// Empty vector means that no problems were found.
+pub fn validate(req: &InvokeRequest) -> Vec<Problem> {
+    let mut validator = Validator::default();
+    validator.collect_definitions(req);
+    validator.check_steps(req);
+    validator.check_outputs(req);
+    validator.problems
+}
+
+/// Validates the request and converts found problems to an error.
+/// Code of the error is the code of the first problem.
+pub fn check(req: &InvokeRequest) -> Result<(), ApiError> {
+    let problems = validate(req);
+    let first = match problems.first() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let description = problems
+        .iter()
+        .map(|p| format!("{}: {}", p.location, p.message))
+        .collect::<Vec<_>>()
+        .join("; ");
+    Err(ApiError {
+        code: first.code,
+        message: format!("request is invalid: {}", description),
+        problems,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+    use crate::{error::ErrorCode, invoke::InvokeRequest};
+
+    fn command(stage: u32, sandbox: &str, stdin: &str) -> serde_json::Value {
+        serde_json::json!({
+            "stage": stage,
+            "action": {
+                "executeCommand": {
+                    "sandboxName": sandbox,
+                    "argv": ["/bin/true"],
+                    "env": [],
+                    "cwd": "/",
+                    "stdio": {"stdin": stdin, "stdout": "null", "stderr": "null"}
+                }
+            }
+        })
+    }
+
+    fn request(steps: Vec<serde_json::Value>) -> InvokeRequest {
+        let mut all_steps = vec![
+            serde_json::json!({"stage": 0, "action": {"openNullFile": {"id": "null"}}}),
+            serde_json::json!({"stage": 0, "action": {"createPipe": {"read": "r", "write": "w"}}}),
+            serde_json::json!({
+                "stage": 0,
+                "action": {
+                    "createSandbox": {
+                        "name": "sb",
+                        "baseImage": "/",
+                        "expose": [],
+                        "limits": {"memory": 1000000, "time": 1000}
+                    }
+                }
+            }),
+        ];
+        all_steps.extend(steps);
+        serde_json::from_value(serde_json::json!({
+            "id": "00000000-0000-0000-0000-000000000000",
+            "steps": all_steps,
+            "inputs": [],
+            "outputs": []
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_valid_request() {
+        let req = request(vec![command(0, "sb", "r"), command(1, "sb", "null")]);
+        assert!(validate(&req).is_empty());
+    }
+
+    #[test]
+    fn test_problems_are_reported() {
+        let req = request(vec![
+            command(0, "unknown", "null"),
+            command(0, "sb", "missing"),
+            command(1, "sb", "r"),
+        ]);
+        let problems = validate(&req);
+        let codes = problems.iter().map(|p| p.code).collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                ErrorCode::UnknownSandbox,
+                ErrorCode::UnknownFileId,
+                ErrorCode::InvalidAction
+            ]
+        );
+        assert_eq!(problems[0].location, "steps[3].sandboxName");
+        assert_eq!(problems[1].location, "steps[4].stdio.stdin");
+        assert_eq!(problems[2].location, "steps[5].stdio.stdin");
+    }
+}
@@ -0,0 +1,74 @@
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::InvokeError,
+};
+
+/// Errors returned by the client.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("transport error")]
+    Transport(#[from] hyper::Error),
+    #[error("failed to serialize request or parse response")]
+    Serialization(#[from] serde_json::Error),
+    /// Invoker failed to execute the request.
+    #[error("{}", describe_invoke_error(.0))]
+    Invoke(Box<InvokeError>),
+    /// Request was rejected by the server before execution, e.g. job
+    /// with the same id already exists. Shim rejections are reported as
+    /// `Invoke` errors.
+    #[error("request rejected: {0}")]
+    Rejected(ApiError),
+    /// Requested object (e.g. job or error report) was not found.
+    #[error("not found")]
+    NotFound,
+    /// Server returned unexpected response.
+    #[error("unexpected response (status {status}, error id {error_id:?}): {body}")]
+    UnexpectedResponse {
+        status: u16,
+        /// Value of the `Error-UUID` header, if present
+        error_id: Option<uuid::Uuid>,
+        body: String,
+    },
+}
+
+fn describe_invoke_error(err: &InvokeError) -> String {
+    format!(
+        "invocation failed (code {:?}, error id {}): {}",
+        err.code,
+        err.error_id.to_hyphenated(),
+        err.error_chain.join(": ")
+    )
+}
+
+impl Error {
+    /// Returns error id which can be used to find the error on the server,
+    /// if it is known.
+    pub fn error_id(&self) -> Option<uuid::Uuid> {
+        match self {
+            Error::Invoke(err) => Some(err.error_id),
+            Error::UnexpectedResponse { error_id, .. } => *error_id,
+            _ => None,
+        }
+    }
+
+    /// Returns machine-readable error code, if server provided it.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Invoke(err) => Some(err.code),
+            Error::Rejected(err) => Some(err.code),
+            _ => None,
+        }
+    }
+
+    /// Returns true if retrying the same request can succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Transport(_) => true,
+            Error::Invoke(err) => !err.code.is_user_error(),
+            Error::UnexpectedResponse { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Async client for the invoker and shim HTTP APIs.
+//!
+//! Server is addressed the same way as in invoker's `--listen-address`:
+//! `tcp://<ip>:<port>` or `unix:<path>`.
+
+mod error;
+mod outputs;
+
+pub use error::Error;
+pub use outputs::{decode_output, encode_input, get_output};
+
+use hyper::{header, Body, Method, StatusCode};
+use invoker_api::{
+    error::{ApiError, ErrorReport},
+    invoke::{InvokeError, InvokeRequest, InvokeResponse},
+    jobs::{JobCreated, JobInfo},
+    shims::ShimResponse,
+};
+use serde::de::DeserializeOwned;
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+
+/// Address of the invoker or shim.
+#[derive(Debug, Clone)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Uds(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = |msg: &str| Error::InvalidAddress(format!("{}: {}", s, msg));
+        let u = url::Url::parse(s).map_err(|err| invalid(&err.to_string()))?;
+        match u.scheme() {
+            "tcp" => {
+                let addr = u.host_str().ok_or_else(|| invalid("address missing"))?;
+                let ip = addr.parse().map_err(|_| invalid("address is not IP"))?;
+                let port = u.port().ok_or_else(|| invalid("port missing"))?;
+                Ok(Address::Tcp(SocketAddr::new(ip, port)))
+            }
+            "unix" => Ok(Address::Uds(u.path().into())),
+            _ => Err(invalid("unknown scheme")),
+        }
+    }
+}
+
+enum Transport {
+    Tcp {
+        client: hyper::Client<hyper::client::HttpConnector>,
+        addr: SocketAddr,
+    },
+    Uds {
+        client: hyper::Client<hyperlocal::UnixConnector>,
+        path: PathBuf,
+    },
+}
+
+/// Raw server response
+struct Response {
+    status: StatusCode,
+    error_id: Option<uuid::Uuid>,
+    body: hyper::body::Bytes,
+}
+
+/// Invoker client. Can also be used to talk to the shim.
+pub struct Client {
+    transport: Transport,
+}
+
+impl Client {
+    pub fn new(address: Address) -> Self {
+        let transport = match address {
+            Address::Tcp(addr) => Transport::Tcp {
+                client: hyper::Client::new(),
+                addr,
+            },
+            Address::Uds(path) => Transport::Uds {
+                client: hyper::Client::builder().build(hyperlocal::UnixConnector),
+                path,
+            },
+        };
+        Client { transport }
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response, Error> {
+        let uri: hyper::Uri = match &self.transport {
+            Transport::Tcp { addr, .. } => format!("http://{}{}", addr, path).parse().map_err(
+                |err: hyper::http::uri::InvalidUri| Error::InvalidAddress(err.to_string()),
+            )?,
+            Transport::Uds { path: socket, .. } => hyperlocal::Uri::new(socket, path).into(),
+        };
+        let builder = hyper::Request::builder().method(method).uri(uri);
+        let req = match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body)),
+            None => builder.body(Body::empty()),
+        }
+        .expect("incorrect request");
+        tracing::debug!(method = %req.method(), path, "sending request");
+        let resp = match &self.transport {
+            Transport::Tcp { client, .. } => client.request(req).await?,
+            Transport::Uds { client, .. } => client.request(req).await?,
+        };
+        let status = resp.status();
+        let error_id = resp
+            .headers()
+            .get("Error-UUID")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        Ok(Response {
+            status,
+            error_id,
+            body,
+        })
+    }
+
+    /// Returns response body if request succeeded, and maps error
+    /// responses otherwise.
+    fn check(resp: Response) -> Result<hyper::body::Bytes, Error> {
+        if resp.status.is_success() {
+            return Ok(resp.body);
+        }
+        if resp.status == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound);
+        }
+        if resp.error_id.is_some() {
+            if let Ok(err) = serde_json::from_slice::<InvokeError>(&resp.body) {
+                return Err(Error::Invoke(Box::new(err)));
+            }
+        } else if resp.status.is_client_error() {
+            if let Ok(err) = serde_json::from_slice::<ApiError>(&resp.body) {
+                return Err(Error::Rejected(err));
+            }
+        }
+        Err(Error::UnexpectedResponse {
+            status: resp.status.as_u16(),
+            error_id: resp.error_id,
+            body: String::from_utf8_lossy(&resp.body).into_owned(),
+        })
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<T, Error> {
+        let resp = self.send(method, path, body).await?;
+        let body = Self::check(resp)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Executes the request and waits for completion (`POST /exec`).
+    pub async fn exec(&self, req: &InvokeRequest) -> Result<InvokeResponse, Error> {
+        let body = serde_json::to_vec(req)?;
+        self.call(Method::POST, "/exec", Some(body)).await
+    }
+
+    /// Starts executing the request in background (`POST /jobs`).
+    pub async fn create_job(&self, req: &InvokeRequest) -> Result<JobCreated, Error> {
+        let body = serde_json::to_vec(req)?;
+        self.call(Method::POST, "/jobs", Some(body)).await
+    }
+
+    /// Returns job status (`GET /jobs/{id}`).
+    pub async fn get_job(&self, id: uuid::Uuid) -> Result<JobInfo, Error> {
+        let path = format!("/jobs/{}", id.to_hyphenated());
+        self.call(Method::GET, &path, None).await
+    }
+
+    /// Cancels the job if it is running and forgets it (`DELETE /jobs/{id}`).
+    pub async fn delete_job(&self, id: uuid::Uuid) -> Result<(), Error> {
+        let path = format!("/jobs/{}", id.to_hyphenated());
+        let resp = self.send(Method::DELETE, &path, None).await?;
+        Self::check(resp).map(drop)
+    }
+
+    /// Cancels running request (`POST /cancel/{id}`).
+    pub async fn cancel(&self, id: uuid::Uuid) -> Result<(), Error> {
+        let path = format!("/cancel/{}", id.to_hyphenated());
+        let resp = self.send(Method::POST, &path, None).await?;
+        Self::check(resp).map(drop)
+    }
+
+    /// Returns error details (`GET /errors/{id}`).
+    pub async fn get_error(&self, id: uuid::Uuid) -> Result<ErrorReport, Error> {
+        let path = format!("/errors/{}", id.to_hyphenated());
+        self.call(Method::GET, &path, None).await
+    }
+
+    /// Sends the request to the shim (`POST /on-request`).
+    /// Rejections are returned as `ShimResponse::Error`.
+    pub async fn shim_on_request(&self, req: &serde_json::Value) -> Result<ShimResponse, Error> {
+        let body = serde_json::to_vec(req)?;
+        let resp = self.send(Method::POST, "/on-request", Some(body)).await?;
+        match serde_json::from_slice::<ShimResponse>(&resp.body) {
+            Ok(response) if response.http_status() == resp.status.as_u16() => Ok(response),
+            _ => Self::check(resp).and_then(|body| Ok(serde_json::from_slice(&body)?)),
+        }
+    }
+
+    /// Checks that server is ready (`GET /ready`).
+    pub async fn ready(&self) -> Result<(), Error> {
+        let resp = self.send(Method::GET, "/ready", None).await?;
+        Self::check(resp).map(drop)
+    }
+
+    /// Waits until server is ready, making at most `attempts` checks
+    /// with `interval` between them. Returns the last error on failure.
+    pub async fn wait_ready(&self, attempts: usize, interval: Duration) -> Result<(), Error> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match self.ready().await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt >= attempts => return Err(err),
+                Err(err) => {
+                    tracing::debug!(error = %err, attempt, "server is not ready yet");
+                    tokio::time::sleep(interval).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use invoker_api::error::ErrorCode;
+
+    #[test]
+    fn test_parse_address() {
+        let addr: Address = "tcp://127.0.0.1:8000".parse().unwrap();
+        assert!(matches!(addr, Address::Tcp(a) if a.port() == 8000));
+        let addr: Address = "unix:/run/invoker.sock".parse().unwrap();
+        assert!(matches!(addr, Address::Uds(p) if p.to_str() == Some("/run/invoker.sock")));
+        assert!("http://localhost".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_rejection() {
+        let resp = Response {
+            status: StatusCode::CONFLICT,
+            error_id: None,
+            body: br#"{"code": "duplicateRequestId", "message": "job exists"}"#[..].into(),
+        };
+        let err = Client::check(resp).unwrap_err();
+        assert_eq!(err.code(), Some(ErrorCode::DuplicateRequestId));
+        assert!(matches!(err, Error::Rejected(_)));
+    }
+}
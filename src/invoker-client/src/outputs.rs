@@ -0,0 +1,24 @@
+//! Helpers for binary data in requests and responses.
+
+use invoker_api::invoke::{InputSource, InvokeResponse, Output, OutputData};
+
+/// Decodes output data.
+/// Returns `None` if the output is missing.
+pub fn decode_output(data: &OutputData) -> Result<Option<Vec<u8>>, base64::DecodeError> {
+    match data {
+        OutputData::InlineBase64(data) => base64::decode(data).map(Some),
+        OutputData::None => Ok(None),
+    }
+}
+
+/// Finds output with the given name.
+pub fn get_output<'a>(response: &'a InvokeResponse, name: &str) -> Option<&'a Output> {
+    response.outputs.iter().find(|output| output.name == name)
+}
+
+/// Returns input source providing given binary data.
+pub fn encode_input(data: &[u8]) -> InputSource {
+    InputSource::InlineBase64 {
+        data: base64::encode(data),
+    }
+}
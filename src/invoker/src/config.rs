@@ -1,22 +1,101 @@
+//! Invoker configuration file.
+//! Config is loaded from the file passed as `--config`, in TOML or YAML
+//! format (chosen by the file extension). Keys are named after the
+//! corresponding command-line flags, and flags take priority over the config.
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
-    /// If enabled, invoker will directly mount host filesystem instead of
-    /// toolchain image.
+    /// Skip optional system checks.
     #[serde(default)]
-    pub host_toolchains: bool,
+    pub skip_checks: bool,
+    /// Directory which will contain temporary invocation data.
+    #[serde(alias = "work-root")]
+    pub work_dir: Option<PathBuf>,
+    /// Listen address, e.g. `tcp://0.0.0.0:8000`.
+    pub listen_address: Option<String>,
+    /// Shim address.
+    pub shim: Option<String>,
+    /// User and group id range as `LOW:HIGH`.
+    pub sandbox_id_range: Option<String>,
+    /// Do not cleanup sandboxes.
+    #[serde(default)]
+    pub debug_leak_sandboxes: bool,
+    /// Do not remove resources left by previous invoker runs on startup.
+    #[serde(default)]
+    pub skip_recovery: bool,
+    /// Enables file-based interactive debugging mode.
+    pub interactive_debug_path: Option<PathBuf>,
+    /// Enables HTTP-based interactive debugging mode.
+    pub interactive_debug_url: Option<String>,
+    /// Real time limit for sandboxes which do not specify it,
+    /// as a multiple of their CPU time limit.
+    pub default_wall_time_multiplier: Option<f64>,
     /// Override directories that will be mounted into sandbox.
-    /// E.g. if `expose-host-dirs = ["lib64", "usr/lib"]`,
+    /// E.g. if `expose-rootfs-items = ["lib64", "usr/lib"]`,
     /// then invoker will mount:
     /// - `$SANDBOX_ROOT/lib64` -> `/lib64`
     /// - `$SANDBOX_ROOT/usr/lib` -> `/usr/lib`
+    ///
     /// As usual, all mounts will be no-suid and read-only.
-    #[serde(default)]
-    pub expose_host_dirs: Option<Vec<String>>,
-    /// Directory which will contain temporary invocation data.
-    pub work_root: PathBuf,
+    #[serde(alias = "expose-host-dirs")]
+    pub expose_rootfs_items: Option<Vec<String>>,
+    /// Keep work directories of this many most recent failed requests.
+    pub keep_failed_work_dirs: Option<usize>,
+    /// Keep work directories for this many minutes after request is finished.
+    pub keep_work_dirs_minutes: Option<u64>,
+    /// Wait this many seconds for running requests on shutdown.
+    pub shutdown_grace_seconds: Option<u64>,
+    /// Deprecated and ignored: host filesystem is mounted for sandboxes
+    /// with `/` base image.
+    pub host_toolchains: Option<bool>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let config = match extension {
+            Some("toml") => toml::from_str(&data).context("invalid TOML")?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&data).context("invalid YAML")?,
+            _ => anyhow::bail!("config file must have .toml, .yaml or .yml extension"),
+        };
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn test_formats_are_equivalent() {
+        let toml: Config = toml::from_str(
+            r#"
+            work-dir = "/var/judges"
+            listen-address = "tcp://0.0.0.0:8000"
+            skip-checks = true
+            expose-rootfs-items = ["usr", "lib"]
+            "#,
+        )
+        .unwrap();
+        let yaml: Config = serde_yaml::from_str(
+            "
+            work-dir: /var/judges
+            listen-address: tcp://0.0.0.0:8000
+            skip-checks: true
+            expose-rootfs-items: [usr, lib]
+            ",
+        )
+        .unwrap();
+        assert_eq!(format!("{:?}", toml), format!("{:?}", yaml));
+        assert!(serde_yaml::from_str::<Config>("unknown-key: 1").is_err());
+        let legacy: Config = toml::from_str("host-toolchains = true").unwrap();
+        assert_eq!(legacy.host_toolchains, Some(true));
+    }
 }
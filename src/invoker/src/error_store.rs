@@ -0,0 +1,77 @@
+//! Keeps recent errors, so that clients can look them up by id
+use invoker_api::error::ErrorReport;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// When the store contains this many errors, oldest errors are forgotten.
+const MAX_STORED_ERRORS: usize = 4096;
+
+#[derive(Default)]
+struct State {
+    errors: HashMap<uuid::Uuid, ErrorReport>,
+    /// Error ids, from oldest to newest
+    order: VecDeque<uuid::Uuid>,
+}
+
+/// Bounded in-memory storage for error reports
+pub struct ErrorStore {
+    state: Mutex<State>,
+}
+
+impl ErrorStore {
+    pub fn new() -> Self {
+        ErrorStore {
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Remembers error with the given id
+    pub fn record(&self, id: uuid::Uuid, err: &anyhow::Error) {
+        let report = ErrorReport {
+            id,
+            message: format!("{:#}", err),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0),
+        };
+        let mut state = self.state.lock().unwrap();
+        state.errors.insert(id, report);
+        state.order.push_back(id);
+        while state.order.len() > MAX_STORED_ERRORS {
+            let evicted = state.order.pop_front().unwrap();
+            state.errors.remove(&evicted);
+        }
+    }
+
+    pub fn get(&self, id: uuid::Uuid) -> Option<ErrorReport> {
+        self.state.lock().unwrap().errors.get(&id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorStore, MAX_STORED_ERRORS};
+    use anyhow::Context as _;
+
+    #[test]
+    fn test_oldest_errors_are_forgotten() {
+        let store = ErrorStore::new();
+        let ids = (0..=MAX_STORED_ERRORS)
+            .map(|_| uuid::Uuid::new_v4())
+            .collect::<Vec<_>>();
+        for id in &ids {
+            let err = Err::<(), _>(anyhow::anyhow!("disk is full"))
+                .context("failed to create file")
+                .unwrap_err();
+            store.record(*id, &err);
+        }
+        assert!(store.get(ids[0]).is_none());
+        let report = store.get(ids[MAX_STORED_ERRORS]).unwrap();
+        assert_eq!(report.id, ids[MAX_STORED_ERRORS]);
+        assert_eq!(report.message, "failed to create file: disk is full");
+    }
+}
@@ -1,29 +1,45 @@
+mod cgroup;
 mod file;
+mod file_table;
 mod path_resolver;
 mod sandbox;
 mod volume;
 
 pub use sandbox::SandboxGlobalSettings;
 
-use self::{file::File, path_resolver::PathResolver, sandbox::Sandbox, volume::Volume};
+use self::{
+    cgroup::{Cgroup, LimitEvents, MemoryPeak},
+    file::File,
+    file_table::FileTable,
+    path_resolver::PathResolver,
+    sandbox::Sandbox,
+    volume::Volume,
+};
+use crate::error_store::ErrorStore;
 use anyhow::Context;
-use invoker_api::invoke::{
-    Action, ActionResult, CommandResult, EnvVarValue, FileId, Input, InputSource,
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::{
+        Action, ActionResult, Command, CommandResult, EnvVarValue, FileId, Input, InputSource,
+        InvokeRequest, Limits, ResourceUsage, Termination, TerminationReason,
+    },
 };
 use minion::{
     ChildProcessOptions, Handle, InputSpecification, OutputSpecification, StdioSpecification,
 };
 use std::{
-    collections::{
-        hash_map::{Entry, VacantEntry},
-        HashMap,
-    },
+    collections::HashMap,
     path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
+/// How often resource usage of the running command is captured.
+const RESOURCE_USAGE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
 /// Actually executes steps from the InvokeRequest and handles Inputs&Outputs.
 pub struct Executor<'a> {
-    files: HashMap<FileId, File>,
+    files: FileTable,
     /// Map from sandbox name to sandbox object
     sandboxes: HashMap<String, Sandbox>,
     /// map from volume name to volume object
@@ -34,6 +50,8 @@ pub struct Executor<'a> {
     work_dir: &'a Path,
     minion: &'a dyn minion::erased::Backend,
     sandbox_global_settings: &'a SandboxGlobalSettings,
+    /// Spawn errors are recorded here
+    error_store: &'a ErrorStore,
 }
 
 impl<'a> Executor<'a> {
@@ -41,31 +59,33 @@ impl<'a> Executor<'a> {
         work_dir: &'a Path,
         minion: &'a dyn minion::erased::Backend,
         sandbox_global_settings: &'a SandboxGlobalSettings,
-        request_id: uuid::Uuid,
+        error_store: &'a ErrorStore,
+        req: &InvokeRequest,
     ) -> Self {
         Executor {
-            files: HashMap::new(),
+            files: FileTable::new(req),
             sandboxes: HashMap::new(),
             volumes: HashMap::new(),
             path_resolver: PathResolver::new(),
             work_dir,
             minion,
             sandbox_global_settings,
-            request_id,
+            error_store,
+            request_id: req.id,
         }
     }
 
     pub fn add_input(&mut self, input: &Input) -> anyhow::Result<()> {
-        let slot = self.prepare_entry(&input.file_id)?;
+        let slot = self.files.prepare_entry(&input.file_id)?;
         let file = match &input.source {
             InputSource::InlineString { data } => {
                 File::from_buffer(data.as_bytes(), "jjs-invoker")?
             }
             InputSource::InlineBase64 { data } => {
-                let data = base64::decode(&data).context("invalid base64")?;
+                let data = base64::decode(data).context("invalid base64")?;
                 File::from_buffer(&data, "jjs-invoker")?
             }
-            InputSource::LocalFile { path } => File::open_read(&path)?,
+            InputSource::LocalFile { path } => File::open_read(path)?,
         };
         slot.insert(file);
         Ok(())
@@ -76,74 +96,74 @@ impl<'a> Executor<'a> {
     }
 
     pub async fn export(&mut self, id: &FileId) -> anyhow::Result<Vec<u8>> {
-        let file = self.files.get(id).context("unknown file id")?;
+        let file = self.files.get(id)?;
         file.read_all().await
     }
 
-    /// Prepates a slot for later `File` insertion.
-    /// Validates that file_id is unused.
-    fn prepare_entry(&mut self, id: &FileId) -> anyhow::Result<VacantEntry<FileId, File>> {
-        match self.files.entry(id.clone()) {
-            Entry::Occupied(_occ) => {
-                anyhow::bail!("File with id {} already exists", id);
+    /// Starts execution of the action.
+    /// Commands are only spawned by this function, use
+    /// `StartedAction::finish` to wait for them.
+    pub async fn start_action(&mut self, action: &Action) -> anyhow::Result<StartedAction> {
+        let started_at = SystemTime::now();
+        let state = match action {
+            Action::ExecuteCommand(command) => self.spawn_command(command).await?,
+            _ => ActionState::finished(self.run_action(action).await?),
+        };
+        Ok(StartedAction { started_at, state })
+    }
+
+    /// Marks the action as skipped.
+    pub fn skip_action(&mut self, action: &Action) -> StartedAction {
+        if let Action::ExecuteCommand(command) = action {
+            // command will never use its files
+            self.files.release_command_files(command);
+        }
+        StartedAction {
+            started_at: SystemTime::now(),
+            state: ActionState::finished(ActionResult::Skipped),
+        }
+    }
+
+    /// Kills all processes in all sandboxes and unmounts volumes.
+    /// Used when request is cancelled. Running commands will finish
+    /// shortly after this call.
+    pub fn teardown(&mut self) {
+        for (name, sandbox) in &self.sandboxes {
+            tracing::info!(sandbox = name.as_str(), "killing sandbox");
+            if let Err(err) = sandbox.raw_sandbox().kill() {
+                tracing::warn!(
+                    sandbox = name.as_str(),
+                    error = %format!("{:#}", err),
+                    "failed to kill sandbox"
+                );
             }
-            Entry::Vacant(v) => Ok(v),
         }
+        // volumes are unmounted on drop
+        self.volumes.clear();
     }
 
-    pub async fn run_action(&mut self, action: &Action) -> anyhow::Result<ActionResult> {
+    async fn run_action(&mut self, action: &Action) -> anyhow::Result<ActionResult> {
         match action {
-            Action::CreateFile {
-                id,
-                readable,
-                writeable,
-            } => {
-                let file_path = self.work_dir.join(&format!("files/{}", id));
-                let slot = self.prepare_entry(id)?;
-                let open_func = match (*readable, *writeable) {
-                    (false, false) => anyhow::bail!("Neither readable nor writeable flags are set"),
-                    (true, false) => |p| File::open_read(p),
-                    (false, true) => |p| File::open_write(p),
-                    (true, true) => |p| File::open_read_write(p),
-                };
-                let file = open_func(&file_path).context("failed to create file")?;
-                slot.insert(file);
-                Ok(ActionResult::CreateFile)
-            }
-            Action::OpenFile { path, id } => {
-                let path = self.path_resolver.resolve(path)?;
-                let slot = self.prepare_entry(id)?;
-                let file = File::open_read(&path)
-                    .with_context(|| format!("failed to open {}", path.display()))?;
-                slot.insert(file);
-                Ok(ActionResult::OpenFile)
-            }
-            Action::OpenNullFile { id } => {
-                let slot = self.prepare_entry(id)?;
-                let file = File::open_null().context("failed to open null file")?;
-                slot.insert(file);
-                Ok(ActionResult::OpenNullFile)
-            }
-            Action::CreatePipe { read, write } => {
-                // unfortunately, we create pipe before checking IDs are unused.
-                // however it's not big issue, since pipe creation usually doesn't fail,
-                // as opposed to e.g. opening files.
-                let (reader, writer) = File::pipe().context("failed to create pipe")?;
-                let slot_reader = self.prepare_entry(read)?;
-                slot_reader.insert(reader);
-                let slot_writer = self.prepare_entry(write)?;
-                slot_writer.insert(writer);
-                Ok(ActionResult::CreatePipe)
+            Action::CreateFile { .. }
+            | Action::OpenFile { .. }
+            | Action::OpenNullFile { .. }
+            | Action::CreatePipe { .. } => {
+                let files_dir = self.work_dir.join("files");
+                self.files
+                    .run_action(action, &files_dir, &self.path_resolver)
             }
             Action::CreateSandbox(sandbox_settings) => {
                 if self.sandboxes.contains_key(&sandbox_settings.name) {
-                    anyhow::bail!("Sandbox named {} already created", sandbox_settings.name);
+                    anyhow::bail!(ApiError::new(
+                        ErrorCode::DuplicateSandbox,
+                        format!("Sandbox named {} already created", sandbox_settings.name)
+                    ));
                 }
                 let sandbox = Sandbox::create(
                     &self.work_dir.join("sandboxes").join(&sandbox_settings.name),
-                    &*self.minion,
+                    self.minion,
                     sandbox_settings,
-                    &self.sandbox_global_settings,
+                    self.sandbox_global_settings,
                     &self.path_resolver,
                     self.request_id,
                 )
@@ -156,118 +176,18 @@ impl<'a> Executor<'a> {
                     .insert(sandbox_settings.name.clone(), sandbox);
                 Ok(ActionResult::CreateSandbox)
             }
-            Action::ExecuteCommand(command) => {
-                let sandbox = match self.sandboxes.get(&command.sandbox_name) {
-                    Some(s) => s,
-                    None => anyhow::bail!("Unknown sandbox {}", command.sandbox_name),
-                };
-                let sandbox = sandbox.raw_sandbox();
-                if command.argv.is_empty() {
-                    anyhow::bail!("argv must be non-empty");
-                }
-
-                let stdin = self
-                    .files
-                    .get(&command.stdio.stdin)
-                    .context("stdin references unknown file")?;
-                stdin
-                    .check_readable()
-                    .context("stdin is not readable file")?;
-
-                let stdout = self
-                    .files
-                    .get(&command.stdio.stdout)
-                    .context("stdout references unknown file")?;
-                stdout
-                    .check_writable()
-                    .context("stdout is not readable file")?;
-
-                let stderr = self
-                    .files
-                    .get(&command.stdio.stderr)
-                    .context("stderr references unknown file")?;
-                stderr
-                    .check_writable()
-                    .context("stderr is not readable file")?;
-
-                let stdin = stdin.try_clone_inherit()?;
-                let stdout = stdout.try_clone_inherit()?;
-                let stderr = stderr.try_clone_inherit()?;
-
-                stdin.rewind().await?;
-                stdout.rewind().await?;
-                stderr.rewind().await?;
-
-                let stdio = StdioSpecification {
-                    stdin: InputSpecification::handle(Handle::new(stdin.into_raw())),
-                    stdout: OutputSpecification::handle(Handle::new(stdout.into_raw())),
-                    stderr: OutputSpecification::handle(Handle::new(stderr.into_raw())),
-                };
-                let mut opts = ChildProcessOptions {
-                    path: (&command.argv[0]).into(),
-                    arguments: command
-                        .argv
-                        .get(1..)
-                        .unwrap()
-                        .iter()
-                        .map(|arg| arg.into())
-                        .collect(),
-                    environment: Vec::new(),
-                    extra_inherit: Vec::new(),
-                    pwd: command.cwd.as_str().into(),
-                    stdio,
-                };
-                for env in &command.env {
-                    let value = match &env.value {
-                        EnvVarValue::File(id) => {
-                            let file = self.files.get(id).context("env references unknown file")?;
-                            let clone = file
-                                .try_clone_inherit()
-                                .context("failed to create inheritable file copy")?;
-                            clone.rewind().await?;
-                            let s = clone.as_raw().to_string();
-                            opts.extra_inherit.push(Handle::new(clone.into_raw()));
-                            s
-                        }
-                        EnvVarValue::Plain(plain) => plain.clone(),
-                    };
-                    let kv = format!("{}={}", env.name, value);
-                    opts.environment.push(kv.into());
-                }
-                tracing::trace!(options = ?opts, "Creating child process");
-                let mut child_process = match self.minion.spawn(opts, sandbox.clone()) {
-                    Ok(ch) => ch,
-                    Err(err) => {
-                        let spawn_error_id = uuid::Uuid::new_v4();
-                        tracing::info!(error_id = %spawn_error_id.to_hyphenated(), error=?err, "Failed to spawn command");
-                        return Ok(ActionResult::ExecuteCommand(CommandResult {
-                            spawn_error: Some(spawn_error_id),
-                            exit_code: i64::max_value(),
-                            cpu_time: None,
-                            memory: None,
-                        }));
-                    }
-                };
-                let exit_code = child_process
-                    .wait_for_exit()
-                    .context("failed to start child process exit watch")?
-                    .await
-                    .context("wait error")?
-                    .0;
-                // TODO we should report resource usage as part of sandbox, not command
-                let resource_usage = sandbox
-                    .resource_usage()
-                    .context("failed to capture resource usage")?;
-                Ok(ActionResult::ExecuteCommand(CommandResult {
-                    spawn_error: None,
-                    exit_code,
-                    cpu_time: resource_usage.time,
-                    memory: resource_usage.memory,
-                }))
-            }
+            Action::ExecuteCommand(command) => self
+                .spawn_command(command)
+                .await?
+                .finish()
+                .await
+                .map(|(result, _)| result),
             Action::CreateVolume(settings) => {
                 if self.volumes.contains_key(&settings.name) {
-                    anyhow::bail!("Volume with name {} already exists", settings.name);
+                    anyhow::bail!(ApiError::new(
+                        ErrorCode::DuplicateVolume,
+                        format!("Volume with name {} already exists", settings.name)
+                    ));
                 }
                 let volume_dir = self.work_dir.join("volumes").join(&settings.name);
                 let v = Volume::create(settings, &volume_dir)
@@ -279,4 +199,397 @@ impl<'a> Executor<'a> {
             }
         }
     }
+
+    /// Spawns command, but does not wait for its completion.
+    async fn spawn_command(&mut self, command: &Command) -> anyhow::Result<ActionState> {
+        let sandbox = match self.sandboxes.get(&command.sandbox_name) {
+            Some(s) => s,
+            None => anyhow::bail!(ApiError::new(
+                ErrorCode::UnknownSandbox,
+                format!("Unknown sandbox {}", command.sandbox_name)
+            )),
+        };
+        let limits = sandbox.limits().clone();
+        let cgroup = sandbox.cgroup();
+        let sandbox = sandbox.raw_sandbox();
+        if command.argv.is_empty() {
+            anyhow::bail!(ApiError::new(
+                ErrorCode::InvalidAction,
+                "argv must be non-empty"
+            ));
+        }
+
+        let [stdin, stdout, stderr] = self.files.stdio(command)?;
+
+        let stdin = stdin.try_clone_inherit()?;
+        let stdout = stdout.try_clone_inherit()?;
+        let stderr = stderr.try_clone_inherit()?;
+
+        stdin.rewind().await?;
+        stdout.rewind().await?;
+        stderr.rewind().await?;
+
+        let stdio = StdioSpecification {
+            stdin: InputSpecification::handle(Handle::new(stdin.into_raw())),
+            stdout: OutputSpecification::handle(Handle::new(stdout.into_raw())),
+            stderr: OutputSpecification::handle(Handle::new(stderr.into_raw())),
+        };
+        let mut opts = ChildProcessOptions {
+            path: (&command.argv[0]).into(),
+            arguments: command
+                .argv
+                .get(1..)
+                .unwrap()
+                .iter()
+                .map(|arg| arg.into())
+                .collect(),
+            environment: Vec::new(),
+            extra_inherit: Vec::new(),
+            pwd: command.cwd.as_str().into(),
+            stdio,
+        };
+        for env in &command.env {
+            let value = match &env.value {
+                EnvVarValue::File(id) => {
+                    let file = self.files.get(id).context("env references unknown file")?;
+                    let clone = file
+                        .try_clone_inherit()
+                        .context("failed to create inheritable file copy")?;
+                    clone.rewind().await?;
+                    let s = clone.as_raw().to_string();
+                    opts.extra_inherit.push(Handle::new(clone.into_raw()));
+                    s
+                }
+                EnvVarValue::Plain(plain) => plain.clone(),
+            };
+            let kv = format!("{}={}", env.name, value);
+            opts.environment.push(kv.into());
+        }
+        let usage_before = sandbox
+            .resource_usage()
+            .context("failed to capture resource usage")?;
+        let events_before = limit_events(cgroup.as_deref())?;
+        let time_limits_before = TimeLimitFlags::of(&*sandbox)?;
+        let memory_peak = cgroup
+            .as_ref()
+            .and_then(|cgroup| cgroup.track_memory_peak());
+        tracing::trace!(options = ?opts, "Creating child process");
+        let spawn_result = self.minion.spawn(opts, sandbox.clone());
+        let spawned_at = Instant::now();
+        self.files.release_command_files(command);
+        let child_process = match spawn_result {
+            Ok(ch) => ch,
+            Err(err) => {
+                let spawn_error_id = uuid::Uuid::new_v4();
+                tracing::info!(error_id = %spawn_error_id.to_hyphenated(), error=?err, "Failed to spawn command");
+                self.error_store.record(spawn_error_id, &err);
+                return Ok(ActionState::finished(ActionResult::ExecuteCommand(
+                    CommandResult {
+                        spawn_error: Some(spawn_error_id),
+                        exit_code: i64::MAX,
+                        cpu_time: None,
+                        memory: None,
+                        wall_time: None,
+                        sandbox_usage: None,
+                        termination: None,
+                    },
+                )));
+            }
+        };
+        Ok(ActionState::Running(RunningCommand {
+            child_process,
+            sandbox,
+            limits,
+            usage_before,
+            cgroup,
+            events_before,
+            time_limits_before,
+            memory_peak,
+            spawned_at,
+        }))
+    }
+}
+
+/// Action that has been started, but possibly is not completed yet.
+pub struct StartedAction {
+    started_at: SystemTime,
+    state: ActionState,
+}
+
+impl StartedAction {
+    /// Waits until action is completed and returns its result.
+    /// This function does not borrow the `Executor`, so several
+    /// actions can be awaited simultaneously.
+    pub async fn finish(self) -> anyhow::Result<FinishedAction> {
+        let (result, finished_at) = self.state.finish().await?;
+        Ok(FinishedAction {
+            result,
+            started_at: self.started_at,
+            finished_at,
+        })
+    }
+}
+
+/// Result of the action, with wall-clock timestamps.
+pub struct FinishedAction {
+    pub result: ActionResult,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+}
+
+enum ActionState {
+    /// Action is already completed
+    Finished(ActionResult, SystemTime),
+    /// Command was spawned and is running now
+    Running(RunningCommand),
+}
+
+impl ActionState {
+    fn finished(result: ActionResult) -> Self {
+        ActionState::Finished(result, SystemTime::now())
+    }
+
+    async fn finish(self) -> anyhow::Result<(ActionResult, SystemTime)> {
+        match self {
+            ActionState::Finished(res, finished_at) => Ok((res, finished_at)),
+            ActionState::Running(cmd) => {
+                let res = cmd.wait().await?;
+                Ok((ActionResult::ExecuteCommand(res), SystemTime::now()))
+            }
+        }
+    }
+}
+
+/// Command that was successfully spawned.
+struct RunningCommand {
+    child_process: Box<dyn minion::erased::ChildProcess>,
+    sandbox: Arc<dyn minion::erased::Sandbox>,
+    /// Limits of the sandbox
+    limits: Limits,
+    /// Sandbox resource usage before the command was spawned
+    usage_before: minion::ResourceUsage,
+    /// None if sandbox does not have its own cgroup
+    cgroup: Option<Arc<Cgroup>>,
+    /// Limit violations in the sandbox before the command was spawned
+    events_before: LimitEvents,
+    /// Time limit violations in the sandbox before the command was spawned
+    time_limits_before: TimeLimitFlags,
+    /// None if peak memory usage can not be measured per command, in this
+    /// case peak usage of the whole sandbox is reported
+    memory_peak: Option<MemoryPeak>,
+    spawned_at: Instant,
+}
+
+impl RunningCommand {
+    async fn wait(mut self) -> anyhow::Result<CommandResult> {
+        let mut idle = IdleTracker::new(
+            self.limits.idle_time.map(Duration::from_millis),
+            self.usage_before.time,
+            Instant::now(),
+        );
+        let mut idle_time_limit_exceeded = false;
+        let exit_code = {
+            let mut exit_future = self
+                .child_process
+                .wait_for_exit()
+                .context("failed to start child process exit watch")?;
+            let mut poll_interval = tokio::time::interval(RESOURCE_USAGE_POLL_INTERVAL);
+            loop {
+                tokio::select! {
+                    res = &mut exit_future => break res.context("wait error")?.0,
+                    _ = poll_interval.tick() => {
+                        let usage = self
+                            .sandbox
+                            .resource_usage()
+                            .context("failed to capture resource usage")?;
+                        let is_idle = idle.update(usage.time, Instant::now());
+                        if is_idle && !idle_time_limit_exceeded {
+                            tracing::info!("killing sandbox: idle time limit exceeded");
+                            idle_time_limit_exceeded = true;
+                            self.sandbox.kill().context("failed to kill idle sandbox")?;
+                        }
+                    }
+                }
+            }
+        };
+        let wall_time = self.spawned_at.elapsed();
+        let resource_usage = self
+            .sandbox
+            .resource_usage()
+            .context("failed to capture resource usage")?;
+        let peak_memory = match &mut self.memory_peak {
+            Some(memory_peak) => Some(
+                memory_peak
+                    .read()
+                    .context("failed to read peak memory usage")?,
+            ),
+            // Sandbox peak is an upper bound of the command peak. It is exact
+            // if the sandbox reached its peak while this command was running,
+            // e.g. for the first command in the sandbox.
+            None => resource_usage.memory,
+        };
+        let cpu_time = match (resource_usage.time, self.usage_before.time) {
+            (Some(after), Some(before)) => Some(after.saturating_sub(before)),
+            _ => None,
+        };
+        let events = limit_events(self.cgroup.as_deref())?.since(self.events_before);
+        let time_limits = TimeLimitFlags::of(&*self.sandbox)?.since(self.time_limits_before);
+        let mut termination = Termination {
+            reason: TerminationReason::Exited,
+            signal: signal_of(exit_code),
+            cpu_time_limit_exceeded: time_limits.cpu,
+            real_time_limit_exceeded: time_limits.real,
+            idle_time_limit_exceeded,
+            memory_limit_exceeded: events.oom_kills > 0,
+            process_limit_exceeded: events.process_limit_hits > 0,
+        };
+        termination.reason = termination_reason(&termination);
+        Ok(CommandResult {
+            spawn_error: None,
+            exit_code,
+            cpu_time,
+            memory: peak_memory,
+            wall_time: Some(wall_time.as_nanos() as u64),
+            sandbox_usage: Some(ResourceUsage {
+                cpu_time: resource_usage.time,
+                memory: resource_usage.memory,
+            }),
+            termination: Some(termination),
+        })
+    }
+}
+
+/// Detects commands which do not use CPU for too long, e.g. because they
+/// wait for input which never comes.
+struct IdleTracker {
+    limit: Option<Duration>,
+    last_cpu_time: Option<u64>,
+    last_cpu_progress: Instant,
+}
+
+impl IdleTracker {
+    fn new(limit: Option<Duration>, cpu_time: Option<u64>, now: Instant) -> Self {
+        IdleTracker {
+            limit,
+            last_cpu_time: cpu_time,
+            last_cpu_progress: now,
+        }
+    }
+
+    /// Accounts CPU time usage of the sandbox captured at `now`.
+    /// Returns true if the limit is exceeded.
+    fn update(&mut self, cpu_time: Option<u64>, now: Instant) -> bool {
+        if cpu_time != self.last_cpu_time {
+            self.last_cpu_time = cpu_time;
+            self.last_cpu_progress = now;
+            return false;
+        }
+        self.limit
+            .is_some_and(|limit| now.duration_since(self.last_cpu_progress) > limit)
+    }
+}
+
+/// Returns limit violation counters of the sandbox cgroup.
+/// Violations are not tracked for sandboxes without their own cgroup.
+fn limit_events(cgroup: Option<&Cgroup>) -> anyhow::Result<LimitEvents> {
+    match cgroup {
+        Some(cgroup) => cgroup
+            .events()
+            .context("failed to read sandbox limit events"),
+        None => Ok(LimitEvents::default()),
+    }
+}
+
+/// Time limit violations of the sandbox. Minion tracks time limits for the
+/// whole sandbox, and once a limit is exceeded, it stays exceeded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct TimeLimitFlags {
+    cpu: bool,
+    real: bool,
+}
+
+impl TimeLimitFlags {
+    fn of(sandbox: &dyn minion::erased::Sandbox) -> anyhow::Result<Self> {
+        Ok(TimeLimitFlags {
+            cpu: sandbox
+                .check_cpu_tle()
+                .context("failed to check CPU time limit")?,
+            real: sandbox
+                .check_real_tle()
+                .context("failed to check real time limit")?,
+        })
+    }
+
+    /// Returns limits which were exceeded since `before`, so that only the
+    /// command which was running at that moment reports the violation.
+    fn since(self, before: TimeLimitFlags) -> TimeLimitFlags {
+        TimeLimitFlags {
+            cpu: self.cpu && !before.cpu,
+            real: self.real && !before.real,
+        }
+    }
+}
+
+/// Minion reports processes killed by a signal using negated signal number
+/// as an exit code.
+fn signal_of(exit_code: i64) -> Option<i32> {
+    if exit_code < 0 && exit_code >= -i64::from(i32::MAX) {
+        Some(-exit_code as i32)
+    } else {
+        None
+    }
+}
+
+/// Chooses main termination reason based on other fields.
+fn termination_reason(termination: &Termination) -> TerminationReason {
+    if termination.cpu_time_limit_exceeded {
+        TerminationReason::CpuTimeLimitExceeded
+    } else if termination.real_time_limit_exceeded {
+        TerminationReason::RealTimeLimitExceeded
+    } else if termination.idle_time_limit_exceeded {
+        TerminationReason::IdleTimeExceeded
+    } else if termination.memory_limit_exceeded {
+        TerminationReason::MemoryLimitExceeded
+    } else if termination.process_limit_exceeded {
+        TerminationReason::ProcessLimitExceeded
+    } else if termination.signal.is_some() {
+        TerminationReason::Signaled
+    } else {
+        TerminationReason::Exited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdleTracker, TimeLimitFlags};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_idle_tracker() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut idle = IdleTracker::new(Some(Duration::from_millis(100)), Some(0), start);
+        assert!(!idle.update(Some(0), at(50)));
+        // CPU time usage resets the idle period
+        assert!(!idle.update(Some(10), at(90)));
+        assert!(!idle.update(Some(10), at(150)));
+        assert!(idle.update(Some(10), at(200)));
+
+        let mut unlimited = IdleTracker::new(None, Some(0), start);
+        assert!(!unlimited.update(Some(0), at(1000)));
+    }
+
+    #[test]
+    fn test_time_limit_is_reported_by_one_command() {
+        let exceeded = TimeLimitFlags {
+            cpu: true,
+            real: false,
+        };
+        // first command exceeds the limit
+        let first = exceeded.since(TimeLimitFlags::default());
+        assert_eq!(first, exceeded);
+        // second command runs in the same sandbox and finishes in time
+        let second = exceeded.since(exceeded);
+        assert_eq!(second, TimeLimitFlags::default());
+    }
 }
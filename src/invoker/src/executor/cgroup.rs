@@ -0,0 +1,136 @@
+//! Reads sandbox cgroup statistics which are not exposed by minion.
+//! Only cgroup v2 is supported.
+use crate::init::CGROUP_PREFIX;
+use anyhow::Context as _;
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Counters of the limit violations in the cgroup (and its descendants)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LimitEvents {
+    /// Number of processes killed by the OOM killer because of memory limit
+    pub oom_kills: u64,
+    /// Number of `fork` failures because of process count limit
+    pub process_limit_hits: u64,
+}
+
+impl LimitEvents {
+    /// Returns events which happened since `before`
+    pub fn since(self, before: LimitEvents) -> LimitEvents {
+        LimitEvents {
+            oom_kills: self.oom_kills.saturating_sub(before.oom_kills),
+            process_limit_hits: self
+                .process_limit_hits
+                .saturating_sub(before.process_limit_hits),
+        }
+    }
+}
+
+/// Tracks peak memory usage of the cgroup since the tracker was created
+pub struct MemoryPeak(std::fs::File);
+
+impl MemoryPeak {
+    pub fn read(&mut self) -> anyhow::Result<u64> {
+        let mut data = String::new();
+        self.0.seek(SeekFrom::Start(0))?;
+        self.0.read_to_string(&mut data)?;
+        data.trim().parse().context("invalid memory.peak value")
+    }
+}
+
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Finds cgroup of the sandbox by pid of the process running in it.
+    /// Returns None if the process is not in a sandbox cgroup (e.g. when
+    /// cgroups are not delegated to the invoker).
+    pub fn of_process(pid: libc::pid_t) -> anyhow::Result<Option<Self>> {
+        let data = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+            .context("failed to read process cgroup")?;
+        // cgroup v2 entry has form `0::<path>`
+        let path = match data.lines().find_map(|line| line.strip_prefix("0::")) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if !Path::new(path).starts_with(CGROUP_PREFIX) || path == CGROUP_PREFIX {
+            return Ok(None);
+        }
+        Ok(Some(Cgroup {
+            path: Path::new("/sys/fs/cgroup").join(path.trim_start_matches('/')),
+        }))
+    }
+
+    /// Returns current values of the limit violation counters.
+    /// Counters of disabled controllers are zero.
+    pub fn events(&self) -> anyhow::Result<LimitEvents> {
+        Ok(LimitEvents {
+            oom_kills: self.read_counter("memory.events", "oom_kill")?,
+            process_limit_hits: self.read_counter("pids.events", "max")?,
+        })
+    }
+
+    /// Starts tracking peak memory usage.
+    /// Returns None if memory controller is disabled or kernel does not
+    /// support resetting the peak (it was added in Linux 6.12).
+    pub fn track_memory_peak(&self) -> Option<MemoryPeak> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.path.join("memory.peak"))
+            .ok()?;
+        // resets the peak for reads through this file descriptor
+        file.write_all(b"reset\n").ok()?;
+        Some(MemoryPeak(file))
+    }
+
+    /// Reads value from the flat keyed file (`<key> <value>` per line).
+    fn read_counter(&self, file: &str, key: &str) -> anyhow::Result<u64> {
+        let path = self.path.join(file);
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        let value = data
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.trim().parse())
+            .transpose()
+            .with_context(|| format!("invalid {} value in {}", key, path.display()))?;
+        Ok(value.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cgroup;
+
+    #[test]
+    fn test_events() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_hyphenated().to_string());
+        std::fs::create_dir(&path).unwrap();
+        let cgroup = Cgroup { path: path.clone() };
+        // pids controller is disabled
+        std::fs::write(
+            path.join("memory.events"),
+            "low 0\nhigh 0\nmax 5\noom 2\noom_kill 1\noom_group_kill 0\n",
+        )
+        .unwrap();
+        let events = cgroup.events().unwrap();
+        assert_eq!(events.oom_kills, 1);
+        assert_eq!(events.process_limit_hits, 0);
+        std::fs::write(path.join("pids.events"), "max 3\n").unwrap();
+        let since = cgroup.events().unwrap().since(events);
+        assert_eq!(since.oom_kills, 0);
+        assert_eq!(since.process_limit_hits, 3);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -3,6 +3,7 @@ mod linux_impl;
 use std::{fs::OpenOptions, mem::ManuallyDrop, path::Path};
 
 use anyhow::Context;
+use invoker_api::error::{ApiError, ErrorCode};
 use linux_impl::RawFile;
 
 /// What kind of object `File` instance refers to.
@@ -58,14 +59,20 @@ impl File {
     pub fn check_readable(&self) -> anyhow::Result<()> {
         match self.mode {
             Mode::Read | Mode::ReadWrite => Ok(()),
-            Mode::Write => anyhow::bail!("File opened in Write mode can't be used for reads"),
+            Mode::Write => anyhow::bail!(ApiError::new(
+                ErrorCode::InvalidFileMode,
+                "File opened in Write mode can't be used for reads"
+            )),
         }
     }
 
     pub fn check_writable(&self) -> anyhow::Result<()> {
         match self.mode {
             Mode::Write | Mode::ReadWrite => Ok(()),
-            Mode::Read => anyhow::bail!("File opened in Read mode can't be used for writes"),
+            Mode::Read => anyhow::bail!(ApiError::new(
+                ErrorCode::InvalidFileMode,
+                "File opened in Read mode can't be used for writes"
+            )),
         }
     }
 
@@ -0,0 +1,276 @@
+//! Files opened by the invoker on behalf of the request.
+use crate::executor::{file::File, path_resolver::PathResolver};
+use anyhow::Context as _;
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::{
+        Action, ActionResult, Command, EnvVarValue, FileId, InvokeRequest, OutputRequestTarget,
+    },
+};
+use std::{
+    collections::{
+        hash_map::{Entry, VacantEntry},
+        HashMap,
+    },
+    path::Path,
+};
+
+pub struct FileTable {
+    files: HashMap<FileId, File>,
+    /// For each file id, number of commands that use this file and
+    /// were not spawned yet. When this counter drops to zero, invoker
+    /// closes its own handle, so that e.g. pipe readers can observe EOF.
+    /// Files exported as outputs are never tracked here.
+    pending_consumers: HashMap<FileId, usize>,
+}
+
+impl FileTable {
+    pub fn new(req: &InvokeRequest) -> Self {
+        FileTable {
+            files: HashMap::new(),
+            pending_consumers: count_consumers(req),
+        }
+    }
+
+    pub fn get(&self, id: &FileId) -> anyhow::Result<&File> {
+        self.files.get(id).ok_or_else(|| {
+            ApiError::new(ErrorCode::UnknownFileId, format!("unknown file id {}", id)).into()
+        })
+    }
+
+    /// Prepates a slot for later `File` insertion.
+    /// Validates that file_id is unused.
+    pub fn prepare_entry(&mut self, id: &FileId) -> anyhow::Result<VacantEntry<'_, FileId, File>> {
+        match self.files.entry(id.clone()) {
+            Entry::Occupied(_occ) => {
+                anyhow::bail!(ApiError::new(
+                    ErrorCode::DuplicateFileId,
+                    format!("File with id {} already exists", id)
+                ));
+            }
+            Entry::Vacant(v) => Ok(v),
+        }
+    }
+
+    /// Runs an action which only opens files. Files created by `CreateFile`
+    /// are placed in `files_dir`.
+    pub fn run_action(
+        &mut self,
+        action: &Action,
+        files_dir: &Path,
+        path_resolver: &PathResolver,
+    ) -> anyhow::Result<ActionResult> {
+        match action {
+            Action::CreateFile {
+                id,
+                readable,
+                writeable,
+            } => {
+                let file_path = files_dir.join(&id.0);
+                let slot = self.prepare_entry(id)?;
+                let file = create_file(&file_path, *readable, *writeable)?;
+                slot.insert(file);
+                Ok(ActionResult::CreateFile)
+            }
+            Action::OpenFile { path, id } => {
+                let path = path_resolver.resolve(path)?;
+                let slot = self.prepare_entry(id)?;
+                let file = File::open_read(&path)
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                slot.insert(file);
+                Ok(ActionResult::OpenFile)
+            }
+            Action::OpenNullFile { id } => {
+                let slot = self.prepare_entry(id)?;
+                let file = File::open_null().context("failed to open null file")?;
+                slot.insert(file);
+                Ok(ActionResult::OpenNullFile)
+            }
+            Action::CreatePipe { read, write } => {
+                // unfortunately, we create pipe before checking IDs are unused.
+                // however it's not big issue, since pipe creation usually doesn't fail,
+                // as opposed to e.g. opening files.
+                let (reader, writer) = File::pipe().context("failed to create pipe")?;
+                let slot_reader = self.prepare_entry(read)?;
+                slot_reader.insert(reader);
+                let slot_writer = self.prepare_entry(write)?;
+                slot_writer.insert(writer);
+                Ok(ActionResult::CreatePipe)
+            }
+            _ => unreachable!("not a file action"),
+        }
+    }
+
+    /// Returns stdin, stdout and stderr of the command, checking that
+    /// they can be read or written respectively.
+    pub fn stdio(&self, command: &Command) -> anyhow::Result<[&File; 3]> {
+        let stdio = &command.stdio;
+        let stdin = self
+            .get(&stdio.stdin)
+            .context("stdin references unknown file")?;
+        stdin
+            .check_readable()
+            .context("stdin is not readable file")?;
+
+        let stdout = self
+            .get(&stdio.stdout)
+            .context("stdout references unknown file")?;
+        stdout
+            .check_writable()
+            .context("stdout is not writable file")?;
+
+        let stderr = self
+            .get(&stdio.stderr)
+            .context("stderr references unknown file")?;
+        stderr
+            .check_writable()
+            .context("stderr is not writable file")?;
+
+        Ok([stdin, stdout, stderr])
+    }
+
+    /// Must be called after the command was spawned (or failed to spawn,
+    /// or was skipped).
+    /// Closes invoker's handles for files that are no longer needed.
+    pub fn release_command_files(&mut self, command: &Command) {
+        for id in command_file_ids(command) {
+            let cnt = match self.pending_consumers.get_mut(id) {
+                Some(cnt) => cnt,
+                None => continue,
+            };
+            *cnt -= 1;
+            if *cnt == 0 {
+                tracing::debug!(file_id = %id, "closing file: all consumers were spawned");
+                self.pending_consumers.remove(id);
+                self.files.remove(id);
+            }
+        }
+    }
+}
+
+/// Implements `CreateFile` action.
+fn create_file(path: &Path, readable: bool, writeable: bool) -> anyhow::Result<File> {
+    let open_func = match (readable, writeable) {
+        (false, false) => anyhow::bail!(ApiError::new(
+            ErrorCode::InvalidAction,
+            "Neither readable nor writeable flags are set"
+        )),
+        (true, false) => |p| File::open_read(p),
+        (false, true) => |p| File::open_write(p),
+        (true, true) => |p| File::open_read_write(p),
+    };
+    open_func(path).context("failed to create file")
+}
+
+/// Returns all file ids used by the command (possibly with duplicates).
+fn command_file_ids(command: &Command) -> impl Iterator<Item = &FileId> {
+    let stdio = &command.stdio;
+    let env_files = command.env.iter().filter_map(|var| match &var.value {
+        EnvVarValue::File(id) => Some(id),
+        EnvVarValue::Plain(_) => None,
+    });
+    vec![&stdio.stdin, &stdio.stdout, &stdio.stderr]
+        .into_iter()
+        .chain(env_files)
+}
+
+fn count_consumers(req: &InvokeRequest) -> HashMap<FileId, usize> {
+    let mut counts = HashMap::new();
+    for step in &req.steps {
+        if let Action::ExecuteCommand(command) = &step.action {
+            for id in command_file_ids(command) {
+                *counts.entry(id.clone()).or_insert(0) += 1;
+            }
+        }
+    }
+    for output in &req.outputs {
+        if let OutputRequestTarget::File(id) = &output.target {
+            counts.remove(id);
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileTable;
+    use crate::executor::path_resolver::PathResolver;
+    use invoker_api::{
+        builder::RequestBuilder,
+        invoke::{Action, Command, FileId, InvokeRequest, Limits},
+    };
+    use std::path::Path;
+
+    /// Runs all file actions of the request and returns its commands.
+    fn open_files<'r>(req: &'r InvokeRequest, dir: &Path) -> (FileTable, Vec<&'r Command>) {
+        let mut files = FileTable::new(req);
+        let mut commands = Vec::new();
+        for step in &req.steps {
+            match &step.action {
+                Action::ExecuteCommand(command) => commands.push(command),
+                Action::CreateSandbox(_) => {}
+                action => {
+                    files.run_action(action, dir, &PathResolver::new()).unwrap();
+                }
+            }
+        }
+        (files, commands)
+    }
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_hyphenated().to_string());
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_default_stdio_modes() {
+        let mut b = RequestBuilder::new(uuid::Uuid::nil());
+        let sandbox = b.sandbox(Limits::new(1 << 30, 1000), "/").add();
+        b.command(&sandbox, vec!["true"]).add();
+        let req = b.build().unwrap();
+
+        let dir = temp_dir();
+        let (files, commands) = open_files(&req, &dir);
+        assert_eq!(commands.len(), 1);
+        files.stdio(commands[0]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_are_closed_after_last_consumer() {
+        let mut b = RequestBuilder::new(uuid::Uuid::nil());
+        let sandbox = b.sandbox(Limits::new(1 << 30, 1000), "/").add();
+        let pipe = b.pipe();
+        let log = b.create_file();
+        b.command(&sandbox, vec!["writer"])
+            .stdout(&pipe.write)
+            .stderr(&log)
+            .add();
+        b.command(&sandbox, vec!["reader"])
+            .stdin(&pipe.read)
+            .stderr(&log)
+            .add();
+        b.command(&sandbox, vec!["env-reader"])
+            .env_file("INPUT", &pipe.read)
+            .add();
+        b.output_file("log", &log);
+        let req = b.build().unwrap();
+
+        let dir = temp_dir();
+        let (mut files, commands) = open_files(&req, &dir);
+        let is_open = |files: &FileTable, id: &FileId| files.get(id).is_ok();
+        assert_eq!(commands.len(), 3);
+
+        files.release_command_files(commands[0]);
+        assert!(!is_open(&files, pipe.write.id()));
+        assert!(is_open(&files, pipe.read.id()));
+        files.release_command_files(commands[1]);
+        assert!(is_open(&files, pipe.read.id()));
+        files.release_command_files(commands[2]);
+        assert!(!is_open(&files, pipe.read.id()));
+        // outputs are kept until they are exported
+        assert!(is_open(&files, log.id()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,8 +3,10 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::Context;
-use invoker_api::invoke::{PathPrefix, PrefixedPath};
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::{PathPrefix, PrefixedPath},
+};
 
 pub struct PathResolver {
     volumes: HashMap<String, PathBuf>,
@@ -24,21 +26,27 @@ impl PathResolver {
     fn resolve_prefix(&self, prefix: &PathPrefix) -> anyhow::Result<PathBuf> {
         match prefix {
             PathPrefix::Host => Ok("/".into()),
-            PathPrefix::Volume(name) => self
-                .volumes
-                .get(name)
-                .cloned()
-                .with_context(|| format!("volume {} not found", name)),
+            PathPrefix::Volume(name) => self.volumes.get(name).cloned().ok_or_else(|| {
+                ApiError::new(
+                    ErrorCode::UnknownVolume,
+                    format!("volume {} not found", name),
+                )
+                .into()
+            }),
             // we could ban it during validation, but this works too
-            PathPrefix::Extension(_) => {
-                anyhow::bail!("Extension sharedDirSource must be resolved by the shim")
-            }
+            PathPrefix::Extension(_) => anyhow::bail!(ApiError::new(
+                ErrorCode::UnresolvedExtensions,
+                "Extension sharedDirSource must be resolved by the shim"
+            )),
         }
     }
 
     pub fn resolve(&self, src: &PrefixedPath) -> anyhow::Result<PathBuf> {
         if !src.path.is_relative() {
-            anyhow::bail!("prefixed path must be relative")
+            anyhow::bail!(ApiError::new(
+                ErrorCode::PathNotRelative,
+                "prefixed path must be relative"
+            ))
         }
         self.resolve_prefix(&src.prefix).map(|p| p.join(&src.path))
     }
@@ -1,10 +1,14 @@
-use crate::{executor::path_resolver::PathResolver, interactive_debug::Suspender};
+use crate::{
+    executor::{cgroup::Cgroup, path_resolver::PathResolver},
+    interactive_debug::Suspender,
+};
 use anyhow::Context as _;
 use invoker_api::{
     debug::AttachRequest,
-    invoke::{SandboxSettings, SharedDirectoryMode},
+    invoke::{Limits, SandboxSettings, SharedDirectoryMode},
 };
 use minion::{SharedItem, SharedItemKind};
+use serde::Deserialize;
 use std::{
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
@@ -15,6 +19,9 @@ use tokio::fs;
 
 pub struct Sandbox {
     sandbox: Arc<dyn minion::erased::Sandbox>,
+    limits: Limits,
+    /// None if sandbox does not have its own cgroup
+    cgroup: Option<Arc<Cgroup>>,
 }
 
 pub struct SandboxGlobalSettings {
@@ -23,6 +30,9 @@ pub struct SandboxGlobalSettings {
     pub override_id_range: Option<(u32, u32)>,
     pub leak: bool,
     pub allow_fallback_pid_limit: bool,
+    /// Real time limit used for sandboxes that do not specify it,
+    /// relative to their CPU time limit.
+    pub real_time_limit_multiplier: f64,
     pub suspender: Arc<Suspender>,
 }
 
@@ -36,11 +46,35 @@ fn default_process_limit(s: &SandboxGlobalSettings) -> u64 {
     }
 }
 
+/// Returns process count limit of the sandbox: `processCount` from the
+/// request, or the default limit if it is not set. When the fallback limit
+/// is used (i.e. `pids` controller is not available), minion only supports
+/// one process per sandbox, so larger limits are reduced to 1. This is not
+/// reported for each sandbox, because it was reported on startup.
+fn process_limit(limits: &Limits, s: &SandboxGlobalSettings) -> u64 {
+    let limit = limits
+        .process_count
+        .unwrap_or_else(|| default_process_limit(s));
+    if s.allow_fallback_pid_limit {
+        return limit.min(1);
+    }
+    limit
+}
+
 impl Sandbox {
     pub fn raw_sandbox(&self) -> Arc<dyn minion::erased::Sandbox> {
         self.sandbox.clone()
     }
 
+    /// Returns limits this sandbox was created with
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn cgroup(&self) -> Option<Arc<Cgroup>> {
+        self.cgroup.clone()
+    }
+
     pub async fn create(
         sandbox_data_dir: &Path,
         backend: &dyn minion::erased::Backend,
@@ -133,18 +167,17 @@ impl Sandbox {
         }
 
         let cpu_time_limit = Duration::from_millis(settings.limits.time);
-        let real_time_limit = Duration::from_millis(settings.limits.time * 3);
+        let real_time_limit = match settings.limits.wall_time {
+            Some(wall_time) => Duration::from_millis(wall_time),
+            None => cpu_time_limit.mul_f64(global_settings.real_time_limit_multiplier),
+        };
         let chroot_dir = sandbox_data_dir.join("root");
         tokio::fs::create_dir(&chroot_dir)
             .await
             .with_context(|| format!("failed to create chroot dir {}", chroot_dir.display()))?;
         // TODO adjust integer types
         let sandbox_options = minion::SandboxOptions {
-            max_alive_process_count: settings
-                .limits
-                .process_count
-                .unwrap_or_else(|| default_process_limit(global_settings))
-                as _,
+            max_alive_process_count: process_limit(&settings.limits, global_settings) as _,
             memory_limit: settings.limits.memory,
             shared_items,
             isolation_root: chroot_dir,
@@ -159,6 +192,16 @@ impl Sandbox {
         let raw_debug_data = sandbox
             .debug_info()
             .context("failed to get sandbox debugging information")?;
+        let zygote_pid = zygote_pid(&raw_debug_data)?;
+        crate::recovery::record_cgroups(sandbox_data_dir, zygote_pid)
+            .context("failed to record sandbox cgroups")?;
+        apply_rlimits(zygote_pid, &settings.limits).context("failed to apply rlimits")?;
+        let cgroup = Cgroup::of_process(zygote_pid).context("failed to find sandbox cgroup")?;
+        if cgroup.is_none() {
+            tracing::debug!(
+                "sandbox does not have its own cgroup, limit violations are not tracked"
+            );
+        }
         let debug_data = AttachRequest {
             raw: raw_debug_data,
             request_id,
@@ -170,7 +213,11 @@ impl Sandbox {
             .await
             .context("failed to wait for debugger attach")?;
 
-        Ok(Sandbox { sandbox })
+        Ok(Sandbox {
+            sandbox,
+            limits: settings.limits.clone(),
+            cgroup: cgroup.map(Arc::new),
+        })
     }
 
     /// Makes sure that inner sandbox will not be dropped
@@ -190,6 +237,84 @@ static DEFAULT_HOST_MOUNTS: once_cell::sync::Lazy<Vec<String>> = once_cell::sync
     ]
 });
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawDebugInfo {
+    zygote_pid: libc::pid_t,
+}
+
+/// Returns pid of the sandbox zygote, which is the parent of all processes
+/// in the sandbox. Minion does not have an API for it, and reports it only
+/// as a part of debugging information (strace-debugger relies on it too),
+/// so the pid is checked to belong to a running process.
+fn zygote_pid(raw_debug_data: &serde_json::Value) -> anyhow::Result<libc::pid_t> {
+    let debug_info: RawDebugInfo = serde_json::from_value(raw_debug_data.clone())
+        .context("failed to find zygote pid in sandbox debugging information")?;
+    // prlimit(0, ...) would silently apply limits to the invoker itself
+    if debug_info.zygote_pid <= 0 {
+        anyhow::bail!("invalid zygote pid {}", debug_info.zygote_pid);
+    }
+    let pid = nix::unistd::Pid::from_raw(debug_info.zygote_pid);
+    nix::sys::signal::kill(pid, None)
+        .with_context(|| format!("zygote process {} does not exist", pid))?;
+    Ok(debug_info.zygote_pid)
+}
+
+/// Applies resource limits not supported by minion.
+/// Returns an error if some limit was not applied, because sandbox must
+/// never run without requested limits.
+///
+/// Limits are set on the sandbox zygote, so that all processes spawned
+/// later inherit them. Minion does not allow running code in the child
+/// before exec, and setting limits after spawn would race with the command.
+/// Limiting the zygote itself is safe: it does not write files and does
+/// not need a large stack. It does keep a few descriptors open and receives
+/// stdio of every spawned command, so with a very low open files limit
+/// spawning fails with an error instead of running the command unlimited.
+fn apply_rlimits(zygote_pid: libc::pid_t, limits: &Limits) -> anyhow::Result<()> {
+    let rlimits = [
+        (libc::RLIMIT_FSIZE, limits.file_size, "file size"),
+        (libc::RLIMIT_NOFILE, limits.open_files, "open files"),
+        (libc::RLIMIT_STACK, limits.stack_size, "stack size"),
+        (libc::RLIMIT_CORE, limits.core_dump_size, "core dump size"),
+    ];
+    for (resource, value, name) in rlimits.iter() {
+        let value = match value {
+            Some(v) => *v,
+            None => continue,
+        };
+        let rlim = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: value,
+        };
+        // `rlim` is a valid pointer, and we do not request old value.
+        let ret = unsafe { libc::prlimit(zygote_pid, *resource, &rlim, std::ptr::null_mut()) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to set {} limit", name));
+        }
+        let mut applied = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // `applied` is a valid pointer, and we do not set new value.
+        let ret = unsafe { libc::prlimit(zygote_pid, *resource, std::ptr::null(), &mut applied) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to check {} limit", name));
+        }
+        if applied.rlim_cur != value || applied.rlim_max != value {
+            anyhow::bail!(
+                "{} limit was not applied: requested {}, actual {}",
+                name,
+                value,
+                applied.rlim_cur
+            );
+        }
+    }
+    Ok(())
+}
+
 async fn validate_shared_item(item: &SharedItem) {
     if let Err(e) = do_validate_shared_item(item).await {
         tracing::warn!(
@@ -224,3 +349,35 @@ async fn do_validate_shared_item(item: &SharedItem) -> anyhow::Result<()> {
         Err(err) => Err(err).context("path it not accessible to invoker"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::apply_rlimits;
+    use invoker_api::invoke::Limits;
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn test_file_size_limit_is_enforced() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_hyphenated().to_string());
+        // waits until limits are applied, then writes 4096 bytes
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("read _ && head -c 4096 /dev/zero > \"$0\"")
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut limits = Limits::new(1 << 30, 1000);
+        limits.file_size = Some(1024);
+        apply_rlimits(child.id() as libc::pid_t, &limits).unwrap();
+        child.stdin.take().unwrap().write_all(b"\n").unwrap();
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1024);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,11 +1,44 @@
 //! Interprets given request graph
-use std::{cmp::Ordering, fmt};
+use std::{cmp::Ordering, collections::HashMap, fmt};
 
-use invoker_api::invoke::{Action, InvokeRequest};
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::{Action, ActionResult, InvokeRequest, StepCondition},
+};
+
+/// Summary of the finished step, used for evaluating step conditions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+impl StepOutcome {
+    pub fn of(result: &ActionResult) -> Self {
+        match result {
+            ActionResult::ExecuteCommand(res) => {
+                if res.spawn_error.is_none() && res.exit_code == 0 {
+                    StepOutcome::Succeeded
+                } else {
+                    StepOutcome::Failed
+                }
+            }
+            ActionResult::Skipped => StepOutcome::Skipped,
+            _ => StepOutcome::Succeeded,
+        }
+    }
+}
 
 pub struct Interpreter<'a> {
     req: &'a InvokeRequest,
     completed_steps: Vec<usize>,
+    /// Outcomes of the completed steps
+    outcomes: Vec<Option<StepOutcome>>,
+    /// For each step, list of steps that must be finished before it.
+    /// Contains both explicit (`dependsOn`) and implicit (stage-based)
+    /// dependencies.
+    dependencies: Vec<Vec<usize>>,
 }
 
 impl fmt::Debug for Interpreter<'_> {
@@ -17,22 +50,31 @@ impl fmt::Debug for Interpreter<'_> {
 }
 
 impl<'a> Interpreter<'a> {
-    pub fn new(req: &'a InvokeRequest) -> Self {
-        Interpreter {
+    /// Creates new interpreter.
+    /// Returns an error if the request dependency graph is invalid.
+    pub fn new(req: &'a InvokeRequest) -> anyhow::Result<Self> {
+        let dependencies = build_dependencies(req)?;
+        check_acyclic(&dependencies)?;
+        Ok(Interpreter {
             req,
             completed_steps: Vec::new(),
-        }
+            outcomes: vec![None; req.steps.len()],
+            dependencies,
+        })
     }
 
     pub fn is_completed(&self) -> bool {
         self.completed_steps.len() == self.req.steps.len()
     }
 
-    pub fn poll(&mut self, done: &[usize]) -> Vec<usize> {
+    pub fn poll(&mut self, done: &[(usize, StepOutcome)]) -> Vec<usize> {
+        for &(step_id, outcome) in done {
+            self.outcomes[step_id] = Some(outcome);
+        }
         // recode these steps as completed, filtering out duplicates.
         self.completed_steps.extend(
             done.iter()
-                .copied()
+                .map(|(step_id, _)| *step_id)
                 .filter(|x| self.completed_steps.binary_search(x).is_err())
                 .collect::<Vec<_>>(),
         );
@@ -48,37 +90,143 @@ impl<'a> Interpreter<'a> {
         resp
     }
 
+    /// Checks whether condition of the step holds.
+    /// Must only be called for steps returned by `poll`.
+    pub fn check_condition(&self, step_id: usize) -> bool {
+        let step = &self.req.steps[step_id];
+        match &step.condition {
+            None => true,
+            Some(StepCondition::StepSucceeded(id)) => self
+                .req
+                .steps
+                .iter()
+                .position(|s| s.id.as_ref() == Some(id))
+                .is_some_and(|other| self.outcomes[other] == Some(StepOutcome::Succeeded)),
+            Some(StepCondition::NoFailures) => {
+                let mut visited = vec![false; self.req.steps.len()];
+                let mut stack = self.dependencies[step_id].clone();
+                while let Some(dep) = stack.pop() {
+                    if visited[dep] {
+                        continue;
+                    }
+                    visited[dep] = true;
+                    if self.outcomes[dep] != Some(StepOutcome::Succeeded) {
+                        return false;
+                    }
+                    stack.extend_from_slice(&self.dependencies[dep]);
+                }
+                true
+            }
+        }
+    }
+
+    fn is_step_completed(&self, step_id: usize) -> bool {
+        self.completed_steps.binary_search(&step_id).is_ok()
+    }
+
     fn can_run(&self, step_id: usize) -> bool {
-        if self.completed_steps.binary_search(&step_id).is_ok() {
+        if self.is_step_completed(step_id) {
             // already finished
             return false;
         }
-        for other_step in 0..self.req.steps.len() {
-            if self.completed_steps.binary_search(&other_step).is_ok() {
-                continue;
+        self.dependencies[step_id]
+            .iter()
+            .all(|&dep| self.is_step_completed(dep))
+    }
+}
+
+/// Returns true if `other_step` must be finished before `step`
+/// because of stage rules.
+fn is_implicit_dependency(req: &InvokeRequest, other_step: usize, step: usize) -> bool {
+    let other_step = &req.steps[other_step];
+    let step = &req.steps[step];
+    match std::cmp::Ord::cmp(&other_step.stage, &step.stage) {
+        // some earlier stage must finish first
+        Ordering::Less => true,
+        // other step can be required for our.
+        Ordering::Equal => order_in_phase(&other_step.action) < order_in_phase(&step.action),
+        Ordering::Greater => false,
+    }
+}
+
+fn build_dependencies(req: &InvokeRequest) -> anyhow::Result<Vec<Vec<usize>>> {
+    let mut ids = HashMap::new();
+    for (step_id, step) in req.steps.iter().enumerate() {
+        if let Some(id) = &step.id {
+            if ids.insert(id.as_str(), step_id).is_some() {
+                anyhow::bail!(ApiError::new(
+                    ErrorCode::DuplicateStepId,
+                    format!("Step id {} is used more than once", id)
+                ));
+            }
+        }
+    }
+    let mut dependencies = Vec::with_capacity(req.steps.len());
+    for (step_id, step) in req.steps.iter().enumerate() {
+        let mut deps = Vec::new();
+        for other_step in 0..req.steps.len() {
+            if is_implicit_dependency(req, other_step, step_id) {
+                deps.push(other_step);
             }
-            match std::cmp::Ord::cmp(
-                &self.req.steps[other_step].stage,
-                &self.req.steps[step_id].stage,
-            ) {
-                Ordering::Less => {
-                    // some earlier stage has not finished yet
-
-                    return false;
-                }
-                Ordering::Equal => {
-                    if order_in_phase(&self.req.steps[other_step].action)
-                        < order_in_phase(&self.req.steps[step_id].action)
-                    {
-                        // other step can be required for our.
-                        return false;
-                    }
-                }
-                Ordering::Greater => (),
+        }
+        let condition_dep = match &step.condition {
+            Some(StepCondition::StepSucceeded(id)) => Some(id),
+            _ => None,
+        };
+        for dep in step.depends_on.iter().chain(condition_dep) {
+            let dep = *ids.get(dep.as_str()).ok_or_else(|| {
+                ApiError::new(
+                    ErrorCode::UnknownStepId,
+                    format!("Step {} depends on unknown step {}", step_id, dep),
+                )
+            })?;
+            deps.push(dep);
+        }
+        deps.sort_unstable();
+        deps.dedup();
+        dependencies.push(deps);
+    }
+    Ok(dependencies)
+}
+
+/// Verifies that all steps can be executed, i.e. dependency graph
+/// does not contain cycles.
+fn check_acyclic(dependencies: &[Vec<usize>]) -> anyhow::Result<()> {
+    let mut dependents = vec![Vec::new(); dependencies.len()];
+    let mut unfinished_deps_count = Vec::with_capacity(dependencies.len());
+    for (step_id, deps) in dependencies.iter().enumerate() {
+        for &dep in deps {
+            dependents[dep].push(step_id);
+        }
+        unfinished_deps_count.push(deps.len());
+    }
+    let mut queue = (0..dependencies.len())
+        .filter(|&step_id| unfinished_deps_count[step_id] == 0)
+        .collect::<Vec<_>>();
+    let mut finished_count = 0;
+    while let Some(step_id) = queue.pop() {
+        finished_count += 1;
+        for &dependent in &dependents[step_id] {
+            unfinished_deps_count[dependent] -= 1;
+            if unfinished_deps_count[dependent] == 0 {
+                queue.push(dependent);
             }
         }
-        true
     }
+    if finished_count != dependencies.len() {
+        let stuck = (0..dependencies.len())
+            .filter(|&step_id| unfinished_deps_count[step_id] != 0)
+            .map(|step_id| step_id.to_string())
+            .collect::<Vec<_>>();
+        anyhow::bail!(ApiError::new(
+            ErrorCode::DependencyCycle,
+            format!(
+                "Dependency cycle (possibly through stages): steps {} can never run",
+                stuck.join(", ")
+            )
+        ));
+    }
+    Ok(())
 }
 
 fn order_in_phase(s: &Action) -> u8 {
@@ -92,3 +240,51 @@ fn order_in_phase(s: &Action) -> u8 {
         Action::ExecuteCommand(..) => 2,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Interpreter, StepOutcome};
+    use crate::test_util::{request, step};
+    use invoker_api::invoke::StepCondition;
+
+    #[test]
+    fn test_depends_on() {
+        let req = request(vec![
+            step(0, "checker", &["solution"]),
+            step(0, "solution", &[]),
+            step(0, "other", &[]),
+        ]);
+        let mut interp = Interpreter::new(&req).unwrap();
+        assert_eq!(interp.poll(&[]), vec![1, 2]);
+        assert_eq!(interp.poll(&[(1, StepOutcome::Succeeded)]), vec![0, 2]);
+        assert_eq!(
+            interp.poll(&[(0, StepOutcome::Succeeded), (2, StepOutcome::Succeeded)]),
+            Vec::<usize>::new()
+        );
+        assert!(interp.is_completed());
+    }
+
+    #[test]
+    fn test_cycles_are_rejected() {
+        let req = request(vec![step(0, "a", &["b"]), step(0, "b", &["a"])]);
+        assert!(Interpreter::new(&req).is_err());
+        // step from stage 0 can not depend on step from stage 1
+        let req = request(vec![step(0, "a", &["b"]), step(1, "b", &[])]);
+        assert!(Interpreter::new(&req).is_err());
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut run = step(1, "run", &[]);
+        run.condition = Some(StepCondition::StepSucceeded("compile".to_string()));
+        let mut check = step(2, "check", &[]);
+        check.condition = Some(StepCondition::NoFailures);
+        let req = request(vec![step(0, "compile", &[]), run, check]);
+        let mut interp = Interpreter::new(&req).unwrap();
+        assert_eq!(interp.poll(&[]), vec![0]);
+        assert_eq!(interp.poll(&[(0, StepOutcome::Failed)]), vec![1]);
+        assert!(!interp.check_condition(1));
+        assert_eq!(interp.poll(&[(1, StepOutcome::Skipped)]), vec![2]);
+        assert!(!interp.check_condition(2));
+    }
+}
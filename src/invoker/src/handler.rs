@@ -1,23 +1,159 @@
+mod steps;
 mod validate;
 
 use crate::{
+    error_store::ErrorStore,
     executor::{Executor, SandboxGlobalSettings},
     graph_interp::Interpreter,
     print_invoke_request::PrintWrapper,
+    work_dirs::{RetentionPolicy, WorkDirs},
 };
 use anyhow::Context as _;
-use invoker_api::invoke::{InvokeRequest, InvokeResponse, Output, OutputData, OutputRequestTarget};
+use futures_util::future::BoxFuture;
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::{
+        ActionResult, Budget, InvokeRequest, InvokeResponse, Output, OutputData,
+        OutputRequestTarget, StepResult,
+    },
+};
 use minion::{erased::Backend, linux::Settings};
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use steps::{RunningStep, StepRunner};
+use tokio_util::sync::CancellationToken;
 
 pub struct HandlerConfig {
     pub work_dir: PathBuf,
+    /// Which per-request work directories are kept after request is finished
+    pub retention: RetentionPolicy,
 }
 
 pub struct Handler {
-    cfg: HandlerConfig,
+    work_dirs: Arc<WorkDirs>,
     sandbox_global_settings: SandboxGlobalSettings,
     minion_backend: Box<dyn Backend>,
+    error_store: Arc<ErrorStore>,
+    /// Cancellation tokens of the requests being executed
+    running: Mutex<HashMap<uuid::Uuid, CancellationToken>>,
+}
+
+/// Removes request from the list of running requests when dropped
+struct RunningGuard<'a> {
+    handler: &'a Handler,
+    id: uuid::Uuid,
+}
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.handler.running.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn unix_micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Attached as a context to errors caused by the step
+#[derive(thiserror::Error, Debug)]
+#[error("Step {0} failed")]
+pub struct StepFailed(pub usize);
+
+/// Error which happened during request execution
+pub struct HandleError {
+    pub error: anyhow::Error,
+    /// Contains results of the steps which were finished
+    /// before the error, and outputs which were collected.
+    /// None if no step was finished.
+    pub partial_response: Option<InvokeResponse>,
+}
+
+impl HandleError {
+    /// Returns index of the step which caused the error, if any
+    pub fn step_index(&self) -> Option<usize> {
+        self.error.downcast_ref::<StepFailed>().map(|s| s.0)
+    }
+}
+
+/// Reason for stopping request execution early
+#[derive(Debug, Clone, Copy)]
+enum Interruption {
+    Cancelled,
+    BudgetExceeded,
+}
+
+/// Starts steps using the executor
+struct ExecutorRunner<'r, 'e> {
+    exec: &'r mut Executor<'e>,
+    req: &'r InvokeRequest,
+}
+
+impl StepRunner for ExecutorRunner<'_, '_> {
+    fn start(&mut self, step_id: usize, skip: bool) -> BoxFuture<'_, anyhow::Result<RunningStep>> {
+        let action = &self.req.steps[step_id].action;
+        if skip {
+            let started = self.exec.skip_action(action);
+            return Box::pin(async move { Ok(Box::pin(started.finish()) as RunningStep) });
+        }
+        Box::pin(async move {
+            let started = self.exec.start_action(action).await?;
+            Ok(Box::pin(started.finish()) as RunningStep)
+        })
+    }
+
+    fn teardown(&mut self) {
+        self.exec.teardown();
+    }
+}
+
+/// Tracks request budget usage
+struct BudgetTracker {
+    deadline: Option<tokio::time::Instant>,
+    cpu_time_limit: Option<Duration>,
+    cpu_time_used: Duration,
+}
+
+impl BudgetTracker {
+    fn new(budget: Option<&Budget>) -> Self {
+        let now = tokio::time::Instant::now();
+        BudgetTracker {
+            deadline: budget
+                .and_then(|b| b.wall_time)
+                .map(|ms| now + Duration::from_millis(ms)),
+            cpu_time_limit: budget.and_then(|b| b.cpu_time).map(Duration::from_millis),
+            cpu_time_used: Duration::from_secs(0),
+        }
+    }
+
+    fn account(&mut self, result: &ActionResult) {
+        if let ActionResult::ExecuteCommand(res) = result {
+            self.cpu_time_used += Duration::from_nanos(res.cpu_time.unwrap_or(0));
+        }
+    }
+
+    fn is_exceeded(&self) -> bool {
+        let deadline_reached = self
+            .deadline
+            .is_some_and(|deadline| tokio::time::Instant::now() >= deadline);
+        let cpu_time_exhausted = self
+            .cpu_time_limit
+            .is_some_and(|limit| self.cpu_time_used >= limit);
+        deadline_reached || cpu_time_exhausted
+    }
+
+    /// Resolves when deadline is reached
+    async fn deadline_reached(&self) {
+        match self.deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => futures_util::future::pending().await,
+        }
+    }
 }
 
 fn check_system(settings: &Settings) -> anyhow::Result<()> {
@@ -35,7 +171,7 @@ fn setup_minion(
     id_range: Option<(u32, u32)>,
 ) -> anyhow::Result<Box<dyn Backend>> {
     let mut settings = Settings::new();
-    settings.cgroup.name_prefix = "/jjs".into();
+    settings.cgroup.name_prefix = crate::init::CGROUP_PREFIX.into();
     if let Some((low, high)) = id_range {
         settings.uid = minion::linux::UserIdBounds { low, high };
     }
@@ -50,6 +186,7 @@ impl Handler {
     pub async fn new(
         config: HandlerConfig,
         sandbox_global_settings: SandboxGlobalSettings,
+        error_store: Arc<ErrorStore>,
     ) -> anyhow::Result<Self> {
         let backend = setup_minion(
             sandbox_global_settings.skip_system_checks,
@@ -57,13 +194,51 @@ impl Handler {
         )
         .context("failed to initialize minion backend")?;
 
+        let mut retention = config.retention;
+        retention.keep_all |= sandbox_global_settings.leak;
         Ok(Handler {
-            cfg: config,
+            work_dirs: WorkDirs::new(config.work_dir, retention),
             sandbox_global_settings,
             minion_backend: backend,
+            error_store,
+            running: Mutex::new(HashMap::new()),
         })
     }
 
+    /// Must be called on shutdown, after all requests are finished.
+    pub async fn shutdown(&self) {
+        self.work_dirs.flush().await;
+    }
+
+    /// Cancels request with the given id.
+    /// Returns false if there is no such running request.
+    pub fn cancel(&self, id: uuid::Uuid) -> bool {
+        match self.running.lock().unwrap().get(&id) {
+            Some(token) => {
+                tracing::info!(id = %id.to_hyphenated(), "cancelling request");
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn register_running(
+        &self,
+        id: uuid::Uuid,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<RunningGuard<'_>> {
+        let mut running = self.running.lock().unwrap();
+        if running.contains_key(&id) {
+            anyhow::bail!(ApiError::new(
+                ErrorCode::DuplicateRequestId,
+                format!("request with id {} is already running", id.to_hyphenated())
+            ));
+        }
+        running.insert(id, cancel.clone());
+        Ok(RunningGuard { handler: self, id })
+    }
+
     fn print_request(&self, req: &InvokeRequest) {
         let wrapper = PrintWrapper(req);
         let msg = wrapper.print();
@@ -78,7 +253,7 @@ impl Handler {
     ) -> anyhow::Result<Option<Vec<u8>>> {
         match output_req {
             OutputRequestTarget::File(file_id) => exec
-                .export(&file_id)
+                .export(file_id)
                 .await
                 .map(Some)
                 .with_context(|| format!("failed to export file_id {}", file_id)),
@@ -99,20 +274,71 @@ impl Handler {
         }
     }
 
-    #[tracing::instrument(skip(self, req), fields(id = %req.id.to_hyphenated()))]
+    /// Executes the request.
+    /// When `cancel` is triggered, sandboxes are destroyed and partial
+    /// results are returned.
+    #[tracing::instrument(skip(self, req, cancel), fields(id = %req.id.to_hyphenated()))]
     pub async fn handle_invoke_request(
         &self,
         req: &InvokeRequest,
-    ) -> anyhow::Result<InvokeResponse> {
+        cancel: &CancellationToken,
+    ) -> Result<InvokeResponse, HandleError> {
+        let mut response = InvokeResponse {
+            id: req.id,
+            outputs: Vec::new(),
+            actions: Vec::new(),
+            cancelled: false,
+            budget_exceeded: false,
+        };
+        match self
+            .do_handle_invoke_request(req, cancel, &mut response)
+            .await
+        {
+            Ok(()) => Ok(response),
+            Err(error) => Err(HandleError {
+                error,
+                partial_response: if response.actions.is_empty() {
+                    None
+                } else {
+                    Some(response)
+                },
+            }),
+        }
+    }
+
+    async fn do_handle_invoke_request(
+        &self,
+        req: &InvokeRequest,
+        cancel: &CancellationToken,
+        response: &mut InvokeResponse,
+    ) -> anyhow::Result<()> {
         validate::validate_request(req)?;
         self.print_request(req);
-        let per_request_work_dir = self.cfg.work_dir.join(req.id.to_hyphenated().to_string());
-        let mut interp = Interpreter::new(req);
+        let _running_guard = self.register_running(req.id, cancel)?;
+        let work_dir = self
+            .work_dirs
+            .acquire(req.id)
+            .await
+            .context("failed to prepare work directory")?;
+        let res = self.execute_request(req, cancel, response, &work_dir).await;
+        self.work_dirs.release(req.id, res.is_err()).await;
+        res
+    }
+
+    async fn execute_request(
+        &self,
+        req: &InvokeRequest,
+        cancel: &CancellationToken,
+        response: &mut InvokeResponse,
+        work_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let mut interp = Interpreter::new(req).context("invalid step dependencies")?;
         let mut exec = Executor::new(
-            &per_request_work_dir,
+            work_dir,
             &*self.minion_backend,
             &self.sandbox_global_settings,
-            req.id,
+            &self.error_store,
+            req,
         );
 
         for input in &req.inputs {
@@ -120,37 +346,36 @@ impl Handler {
                 .with_context(|| format!("Failed to add input file {}", input.file_id))?;
         }
 
-        let mut response = InvokeResponse {
-            id: req.id,
-            outputs: Vec::new(),
-            actions: Vec::new(),
-        };
-
-        let mut started_steps = Vec::new();
-        let mut poll_input = Vec::new();
-        loop {
-            tracing::trace!(interpreter_state = ?interp, input = ?poll_input, "polling interpreter");
-            let resp = interp.poll(&poll_input);
-            poll_input.clear();
-            if resp.is_empty() {
-                break;
+        let mut budget = BudgetTracker::new(req.budget.as_ref());
+        let interruption = steps::run_steps(
+            &mut interp,
+            &mut ExecutorRunner {
+                exec: &mut exec,
+                req,
+            },
+            cancel,
+            &mut budget,
+            |step_id, finished| {
+                response.actions.push(StepResult {
+                    step_index: step_id,
+                    step_id: req.steps[step_id].id.clone(),
+                    result: finished.result,
+                    started_at: unix_micros(finished.started_at),
+                    finished_at: unix_micros(finished.finished_at),
+                })
+            },
+        )
+        .await?;
+        match interruption {
+            Some(Interruption::Cancelled) => {
+                response.cancelled = true;
+                return Ok(());
             }
-            tracing::trace!(response = ?resp, "interpreter responsed");
-            for step_id in &resp {
-                let step_id = *step_id;
-                if started_steps.contains(&step_id) {
-                    continue;
-                }
-                tracing::info!(step_id = step_id, "Starting step");
-                started_steps.push(step_id);
-                let action_result = exec
-                    .run_action(&req.steps[step_id].action)
-                    .await
-                    .with_context(|| format!("Step {} failed", step_id))?;
-                tracing::info!(step_id = step_id, "Finished step");
-                poll_input.push(step_id);
-                response.actions.push(action_result);
+            Some(Interruption::BudgetExceeded) => {
+                response.budget_exceeded = true;
+                return Ok(());
             }
+            None => (),
         }
         if !interp.is_completed() {
             anyhow::bail!("Internal error: interpreter stuck: no new steps were requested");
@@ -179,6 +404,6 @@ impl Handler {
                 });
             }
         }
-        Ok(response)
+        Ok(())
     }
 }
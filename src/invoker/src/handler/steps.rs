@@ -0,0 +1,313 @@
+//! Step scheduling: each step is started as soon as its dependencies are
+//! finished, independently of other running steps.
+use super::{BudgetTracker, Interruption, StepFailed};
+use crate::{
+    executor::FinishedAction,
+    graph_interp::{Interpreter, StepOutcome},
+};
+use futures_util::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+/// Resolves when the started step is finished
+pub(super) type RunningStep = BoxFuture<'static, anyhow::Result<FinishedAction>>;
+
+/// Starts steps on behalf of `run_steps`
+pub(super) trait StepRunner {
+    /// Starts the step. If `skip` is true, condition of the step does not
+    /// hold, so it must be reported as skipped instead of being executed.
+    fn start(&mut self, step_id: usize, skip: bool) -> BoxFuture<'_, anyhow::Result<RunningStep>>;
+
+    /// Kills everything that is running, so that running steps finish soon.
+    fn teardown(&mut self);
+}
+
+/// Runs all steps of the request. `on_finished` is called for each
+/// finished step, in order of completion.
+/// Returns the reason if execution was interrupted.
+/// After an error or an interruption no new steps are started, but
+/// running steps are waited for, so that their results are not lost.
+pub(super) async fn run_steps(
+    interp: &mut Interpreter<'_>,
+    runner: &mut impl StepRunner,
+    cancel: &CancellationToken,
+    budget: &mut BudgetTracker,
+    mut on_finished: impl FnMut(usize, FinishedAction),
+) -> anyhow::Result<Option<Interruption>> {
+    let mut running = FuturesUnordered::new();
+    let mut started_steps = Vec::new();
+    let mut poll_input = Vec::new();
+    let mut interruption = None;
+    let mut step_error: Option<anyhow::Error> = None;
+    loop {
+        let accepting = step_error.is_none() && interruption.is_none();
+        if accepting {
+            tracing::trace!(interpreter_state = ?interp, input = ?poll_input, "polling interpreter");
+            let resp = interp.poll(&poll_input);
+            poll_input.clear();
+            tracing::trace!(response = ?resp, "interpreter responsed");
+            let ready = resp
+                .into_iter()
+                .filter(|step_id| !started_steps.contains(step_id))
+                .collect::<Vec<_>>();
+            if !ready.is_empty() {
+                if cancel.is_cancelled() {
+                    interruption = Some(Interruption::Cancelled);
+                } else if budget.is_exceeded() {
+                    interruption = Some(Interruption::BudgetExceeded);
+                }
+            }
+            if interruption.is_some() {
+                tracing::info!(reason = ?interruption, "Request interrupted, destroying sandboxes");
+                runner.teardown();
+                continue;
+            }
+            for step_id in ready {
+                started_steps.push(step_id);
+                let skip = !interp.check_condition(step_id);
+                if skip {
+                    tracing::info!(step_id = step_id, "Skipping step: condition does not hold");
+                } else {
+                    tracing::info!(step_id = step_id, "Starting step");
+                }
+                match runner.start(step_id, skip).await {
+                    Ok(step) => running.push(async move { (step_id, step.await) }),
+                    Err(err) => {
+                        step_error = Some(err.context(StepFailed(step_id)));
+                        // do not wait for commands which were already started
+                        runner.teardown();
+                        break;
+                    }
+                }
+            }
+        }
+        if running.is_empty() {
+            break;
+        }
+        let finished = tokio::select! {
+            finished = running.next() => finished,
+            _ = cancel.cancelled(), if accepting => {
+                interruption = Some(Interruption::Cancelled);
+                None
+            }
+            _ = budget.deadline_reached(), if accepting => {
+                interruption = Some(Interruption::BudgetExceeded);
+                None
+            }
+        };
+        let (step_id, finished) = match finished {
+            Some(finished) => finished,
+            None => {
+                tracing::info!(reason = ?interruption, "Request interrupted, destroying sandboxes");
+                runner.teardown();
+                continue;
+            }
+        };
+        match finished {
+            Ok(finished) => {
+                tracing::info!(step_id = step_id, "Finished step");
+                poll_input.push((step_id, StepOutcome::of(&finished.result)));
+                budget.account(&finished.result);
+                on_finished(step_id, finished);
+            }
+            Err(err) => {
+                if step_error.is_none() {
+                    step_error = Some(err.context(StepFailed(step_id)));
+                    runner.teardown();
+                }
+            }
+        }
+    }
+    match step_error {
+        Some(err) => Err(err),
+        None => Ok(interruption),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_steps, RunningStep, StepRunner};
+    use crate::handler::Interruption;
+    use crate::{
+        executor::FinishedAction,
+        graph_interp::Interpreter,
+        handler::BudgetTracker,
+        test_util::{request, step},
+    };
+    use futures_util::future::BoxFuture;
+    use invoker_api::invoke::{ActionResult, Budget, CommandResult, InvokeRequest};
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
+    use tokio::sync::oneshot;
+    use tokio_util::sync::CancellationToken;
+
+    /// Steps with a gate finish only when the gate is opened, other steps
+    /// finish immediately.
+    struct GatedRunner {
+        gates: HashMap<usize, oneshot::Receiver<()>>,
+    }
+
+    impl StepRunner for GatedRunner {
+        fn start(
+            &mut self,
+            step_id: usize,
+            _skip: bool,
+        ) -> BoxFuture<'_, anyhow::Result<RunningStep>> {
+            let gate = self.gates.remove(&step_id);
+            Box::pin(async move {
+                let step: RunningStep = Box::pin(async move {
+                    let started_at = SystemTime::now();
+                    if let Some(gate) = gate {
+                        gate.await.ok();
+                    }
+                    Ok(FinishedAction {
+                        result: ActionResult::OpenNullFile,
+                        started_at,
+                        finished_at: SystemTime::now(),
+                    })
+                });
+                Ok(step)
+            })
+        }
+
+        fn teardown(&mut self) {}
+    }
+
+    /// Runs steps of the request, finishing step `gated` only after step
+    /// `opened_by` is finished. Returns steps in order of completion.
+    /// Panics if the steps can not be finished in such order.
+    async fn run_gated(req: &InvokeRequest, gated: usize, opened_by: usize) -> Vec<usize> {
+        let mut interp = Interpreter::new(req).unwrap();
+        let (open_gate, gate) = oneshot::channel();
+        let mut runner = GatedRunner {
+            gates: vec![(gated, gate)].into_iter().collect(),
+        };
+        let mut open_gate = Some(open_gate);
+        let mut finished = Vec::new();
+        let cancel = CancellationToken::new();
+        let mut budget = BudgetTracker::new(None);
+        let steps = run_steps(
+            &mut interp,
+            &mut runner,
+            &cancel,
+            &mut budget,
+            |step_id, _| {
+                finished.push(step_id);
+                if step_id == opened_by {
+                    open_gate.take().unwrap().send(()).unwrap();
+                }
+            },
+        );
+        let interruption = tokio::time::timeout(Duration::from_secs(10), steps)
+            .await
+            .expect("gated step blocks other steps")
+            .unwrap();
+        assert!(interruption.is_none());
+        assert!(interp.is_completed());
+        finished
+    }
+
+    #[tokio::test]
+    async fn test_same_stage_steps_run_concurrently() {
+        let req = request(vec![step(0, "interactor", &[]), step(0, "solution", &[])]);
+        assert_eq!(run_gated(&req, 0, 1).await, vec![1, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_independent_chains_run_in_parallel() {
+        let req = request(vec![
+            step(0, "long", &[]),
+            step(0, "a", &[]),
+            step(0, "b", &["a"]),
+            step(0, "c", &["b"]),
+        ]);
+        // the long step is finished only after the whole short chain
+        assert_eq!(run_gated(&req, 0, 3).await, vec![1, 2, 3, 0]);
+    }
+
+    /// Each step is a command which uses `cpu_time` milliseconds of CPU time
+    struct CommandRunner {
+        cpu_time: u64,
+        started: Vec<usize>,
+    }
+
+    impl StepRunner for CommandRunner {
+        fn start(
+            &mut self,
+            step_id: usize,
+            _skip: bool,
+        ) -> BoxFuture<'_, anyhow::Result<RunningStep>> {
+            self.started.push(step_id);
+            let result = CommandResult {
+                spawn_error: None,
+                exit_code: 0,
+                cpu_time: Some(Duration::from_millis(self.cpu_time).as_nanos() as u64),
+                memory: None,
+                wall_time: None,
+                sandbox_usage: None,
+                termination: None,
+            };
+            Box::pin(async move {
+                let step: RunningStep = Box::pin(async move {
+                    Ok(FinishedAction {
+                        result: ActionResult::ExecuteCommand(result),
+                        started_at: SystemTime::now(),
+                        finished_at: SystemTime::now(),
+                    })
+                });
+                Ok(step)
+            })
+        }
+
+        fn teardown(&mut self) {}
+    }
+
+    /// Runs steps of the request with the given budget and returns the
+    /// interruption and started steps.
+    async fn run_with_budget(
+        req: &InvokeRequest,
+        budget: &Budget,
+    ) -> (Option<Interruption>, Vec<usize>) {
+        let mut interp = Interpreter::new(req).unwrap();
+        let mut runner = CommandRunner {
+            cpu_time: 60,
+            started: Vec::new(),
+        };
+        let interruption = run_steps(
+            &mut interp,
+            &mut runner,
+            &CancellationToken::new(),
+            &mut BudgetTracker::new(Some(budget)),
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        (interruption, runner.started)
+    }
+
+    #[tokio::test]
+    async fn test_budget_stops_execution() {
+        let req = request(vec![
+            step(0, "a", &[]),
+            step(0, "b", &["a"]),
+            step(0, "c", &["b"]),
+        ]);
+        // each step uses 60ms, so the budget is exhausted by the second step
+        let cpu_budget = Budget {
+            wall_time: None,
+            cpu_time: Some(100),
+        };
+        let (interruption, started) = run_with_budget(&req, &cpu_budget).await;
+        assert!(matches!(interruption, Some(Interruption::BudgetExceeded)));
+        assert_eq!(started, vec![0, 1]);
+
+        let wall_budget = Budget {
+            wall_time: Some(0),
+            cpu_time: None,
+        };
+        let (interruption, started) = run_with_budget(&req, &wall_budget).await;
+        assert!(matches!(interruption, Some(Interruption::BudgetExceeded)));
+        assert!(started.is_empty());
+    }
+}
@@ -1,4 +1,7 @@
-use invoker_api::invoke::{Action, Command, InvokeRequest, SandboxSettings, Step, VolumeSettings};
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::{Action, Command, InvokeRequest, SandboxSettings, Step, VolumeSettings},
+};
 
 fn request_has_extensions(req: &InvokeRequest) -> bool {
     let InvokeRequest {
@@ -6,6 +9,7 @@ fn request_has_extensions(req: &InvokeRequest) -> bool {
         inputs,
         outputs,
         id: _,
+        budget: _,
         ext,
     } = req;
 
@@ -36,6 +40,9 @@ fn step_has_extensions(step: &Step) -> bool {
     let Step {
         stage: _,
         action,
+        id: _,
+        depends_on: _,
+        condition: _,
         ext,
     } = step;
     if !ext.0.is_empty() {
@@ -108,7 +115,11 @@ fn volume_has_extensions(vol: &VolumeSettings) -> bool {
 
 pub(super) fn validate_request(req: &InvokeRequest) -> anyhow::Result<()> {
     if request_has_extensions(req) {
-        anyhow::bail!("Request contains non-empty extensions")
+        anyhow::bail!(ApiError::new(
+            ErrorCode::UnresolvedExtensions,
+            "Request contains non-empty extensions"
+        ))
     }
+    invoker_api::validate::check(req)?;
     Ok(())
 }
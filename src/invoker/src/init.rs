@@ -1,6 +1,110 @@
 //! platform-specific initialization
 use anyhow::Context;
-use nix::sched::CloneFlags;
+use nix::{sched::CloneFlags, unistd::AccessFlags};
+use std::path::Path;
+
+/// Cgroup (relative to the cgroupfs root) containing all sandboxes.
+pub const CGROUP_PREFIX: &str = "/jjs";
+
+/// Cgroup controllers which are required to enforce all limits.
+const REQUIRED_CONTROLLERS: &[&str] = &["pids", "memory", "cpu"];
+
+/// Describes how well sandboxes can be isolated on this host.
+#[derive(Debug)]
+pub struct Isolation {
+    /// Invoker was started by non-root user and runs in its own user
+    /// namespace.
+    pub rootless: bool,
+    /// Required cgroup controllers which are not delegated to the invoker.
+    pub missing_controllers: Vec<&'static str>,
+    /// Sandboxes cgroup exists and is writeable. Minion creates a cgroup
+    /// for each sandbox there, so without it no sandbox can be created.
+    pub cgroup_writeable: bool,
+}
+
+impl Isolation {
+    /// Returns true if some limits can not be enforced, so invoker must
+    /// use fallback limits.
+    pub fn is_degraded(&self) -> bool {
+        !self.missing_controllers.is_empty()
+    }
+
+    /// Lists guarantees which are weaker than in the normal mode.
+    pub fn weakened_guarantees(&self) -> Vec<&'static str> {
+        let mut weakened = Vec::new();
+        if self.rootless {
+            weakened.push("sandboxes run as the invoker user instead of dedicated users");
+        }
+        for controller in &self.missing_controllers {
+            weakened.push(match *controller {
+                "pids" => "process count limit is reduced to 1 for all sandboxes",
+                "memory" => "memory limit may not be enforced",
+                "cpu" => "CPU usage of sandboxes may not be isolated",
+                _ => unreachable!(),
+            });
+        }
+        weakened
+    }
+
+    /// Logs weakened guarantees, if any.
+    pub fn report(&self) {
+        if !self.cgroup_writeable {
+            tracing::error!(
+                "cgroup {} is missing or not writeable, so sandboxes can not be \
+                 created and all requests will fail (run scripts/dev-cgroups.sh \
+                 to fix this)",
+                CGROUP_PREFIX
+            );
+        }
+        if self.is_degraded() {
+            tracing::warn!(
+                missing_controllers = ?self.missing_controllers,
+                "cgroups are not delegated to the invoker, falling back to weaker \
+                 limits (run scripts/dev-cgroups.sh to fix this)"
+            );
+        }
+        for guarantee in self.weakened_guarantees() {
+            tracing::warn!("rootless mode: {}", guarantee);
+        }
+    }
+}
+
+/// Checks delegation of the sandboxes cgroup. Only cgroup v2 is supported.
+fn detect_rootless_isolation() -> Isolation {
+    let cgroup = Path::new("/sys/fs/cgroup").join(CGROUP_PREFIX.trim_start_matches('/'));
+    if nix::unistd::access(&cgroup, AccessFlags::W_OK).is_err() {
+        return Isolation {
+            rootless: true,
+            missing_controllers: REQUIRED_CONTROLLERS.to_vec(),
+            cgroup_writeable: false,
+        };
+    }
+    let enabled =
+        std::fs::read_to_string(cgroup.join("cgroup.subtree_control")).unwrap_or_default();
+    let missing_controllers = REQUIRED_CONTROLLERS
+        .iter()
+        .copied()
+        .filter(|controller| !enabled.split_whitespace().any(|c| c == *controller))
+        .collect();
+    Isolation {
+        rootless: true,
+        missing_controllers,
+        cgroup_writeable: true,
+    }
+}
+
+/// Must be called before unsharing user namespace.
+fn detect_isolation() -> Isolation {
+    if nix::unistd::getuid().is_root() {
+        // Root is able to configure cgroups itself.
+        return Isolation {
+            rootless: false,
+            missing_controllers: Vec::new(),
+            cgroup_writeable: true,
+        };
+    }
+    detect_rootless_isolation()
+}
 
 fn unshare_mount_namespace() -> anyhow::Result<()> {
     nix::sched::unshare(CloneFlags::CLONE_NEWNS).context("unshare() fail")
@@ -29,7 +133,8 @@ fn unshare() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn init() -> anyhow::Result<()> {
+pub fn init() -> anyhow::Result<Isolation> {
+    let isolation = detect_isolation();
     unshare().context("failed to create namespaces")?;
-    Ok(())
+    Ok(isolation)
 }
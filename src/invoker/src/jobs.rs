@@ -0,0 +1,109 @@
+//! Storage for requests executed asynchronously
+use invoker_api::jobs::{JobInfo, JobStatus};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Finished jobs which were not deleted by the client are removed
+/// when their count exceeds this value.
+const MAX_FINISHED_JOBS: usize = 1024;
+
+struct Job {
+    info: JobInfo,
+    /// Used to cancel the job when it is deleted
+    cancel: CancellationToken,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: HashMap<uuid::Uuid, Job>,
+    /// Finished jobs, from oldest to newest
+    finished: VecDeque<uuid::Uuid>,
+}
+
+/// Keeps track of all jobs
+pub struct JobStore {
+    state: Mutex<State>,
+}
+
+impl JobStore {
+    pub fn new() -> Self {
+        JobStore {
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Starts executing job in background. `job` must stop when
+    /// `cancel` is triggered.
+    /// Returns false if job with the same id already exists.
+    pub fn start<F>(self: &Arc<Self>, id: uuid::Uuid, cancel: CancellationToken, job: F) -> bool
+    where
+        F: Future<Output = JobStatus> + Send + 'static,
+    {
+        // lock is held until job is registered, so that `finish` will
+        // always find it.
+        let mut state = self.state.lock().unwrap();
+        if state.jobs.contains_key(&id) {
+            return false;
+        }
+        let this = self.clone();
+        tokio::task::spawn(async move {
+            let status = job.await;
+            this.finish(id, status);
+        });
+        state.jobs.insert(
+            id,
+            Job {
+                info: JobInfo {
+                    id,
+                    status: JobStatus::Running,
+                },
+                cancel,
+            },
+        );
+        true
+    }
+
+    fn finish(&self, id: uuid::Uuid, status: JobStatus) {
+        let mut state = self.state.lock().unwrap();
+        let job = match state.jobs.get_mut(&id) {
+            Some(j) => j,
+            // job was deleted
+            None => return,
+        };
+        job.info.status = status;
+        state.finished.push_back(id);
+        while state.finished.len() > MAX_FINISHED_JOBS {
+            let evicted = state.finished.pop_front().unwrap();
+            tracing::info!(job_id = %evicted.to_hyphenated(), "forgetting finished job");
+            state.jobs.remove(&evicted);
+        }
+    }
+
+    /// Calls `func` with the description of the job.
+    /// Returns None if job does not exist.
+    pub fn with_info<R>(&self, id: uuid::Uuid, func: impl FnOnce(&JobInfo) -> R) -> Option<R> {
+        let state = self.state.lock().unwrap();
+        state.jobs.get(&id).map(|job| func(&job.info))
+    }
+
+    /// Cancels the job (if it is running) and forgets it.
+    /// Returns false if job does not exist.
+    pub fn remove(&self, id: uuid::Uuid) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let job = match state.jobs.remove(&id) {
+            Some(j) => j,
+            None => return false,
+        };
+        if let JobStatus::Running = job.info.status {
+            tracing::info!(job_id = %id.to_hyphenated(), "cancelling job");
+            job.cancel.cancel();
+        } else {
+            state.finished.retain(|x| *x != id);
+        }
+        true
+    }
+}
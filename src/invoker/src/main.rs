@@ -1,13 +1,20 @@
 mod cli_args;
 mod config;
+mod error_store;
 mod executor;
 mod graph_interp;
 mod handler;
 mod init;
 mod interactive_debug;
+mod jobs;
 mod print_invoke_request;
+mod recovery;
 mod server;
 mod shim;
+mod shutdown;
+#[cfg(test)]
+mod test_util;
+mod work_dirs;
 
 use anyhow::Context;
 use clap::Clap;
@@ -15,22 +22,33 @@ use cli_args::IdRange;
 use executor::SandboxGlobalSettings;
 use handler::{Handler, HandlerConfig};
 use shim::ShimClient;
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 use tracing_subscriber::{filter::EnvFilter, fmt::format::FmtSpan};
 
+const DEFAULT_SHUTDOWN_GRACE_SECONDS: u64 = 30;
+/// Used when neither flag nor config specifies the multiplier.
+const DEFAULT_WALL_TIME_MULTIPLIER: f64 = 3.0;
+
 #[derive(Clap, Debug)]
 struct CliArgs {
+    /// Path to the config file (TOML or YAML).
+    /// Config can specify any of the options below, but options passed
+    /// on the command line take priority.
+    #[clap(long)]
+    config: Option<PathBuf>,
     /// Skip optional system checkes.
     #[clap(long)]
     skip_checks: bool,
     /// Directory to store intermediate files.
+    /// Required (either as flag or in config).
     #[clap(long)]
-    work_dir: PathBuf,
+    work_dir: Option<PathBuf>,
     /// Listen address.
     /// Example for TCP: tcp://0.0.0.0:8000
     /// Example for unix sockets: unix:/run/jjs-invoker.sock
+    /// Required (either as flag or in config).
     #[clap(long)]
-    listen_address: server::ListenAddress,
+    listen_address: Option<server::ListenAddress>,
     /// Shim address.
     /// For example, `https://127.0.0.1:8001`
     #[clap(long)]
@@ -63,6 +81,89 @@ struct CliArgs {
     /// to this url and will resume when successful response is returned.
     #[clap(long, conflicts_with = "interactive-debug-path")]
     interactive_debug_url: Option<String>,
+    /// Real time limit for sandboxes which do not specify `wallTime`,
+    /// as a multiple of their CPU time limit. Defaults to 3.
+    #[clap(long)]
+    default_wall_time_multiplier: Option<f64>,
+    /// Item of the host filesystem which is exposed to sandboxes which
+    /// use "/" as a base image (can be repeated).
+    /// Overrides built-in default set of items.
+    #[clap(long)]
+    expose_rootfs_item: Vec<String>,
+    /// Keep work directories of this many most recent failed requests.
+    /// By default, work directory is removed as soon as request is finished.
+    #[clap(long)]
+    keep_failed_work_dirs: Option<usize>,
+    /// Keep work directories for this many minutes after request is finished.
+    /// Directories kept because of `--keep-failed-work-dirs` are removed only
+    /// after this time too.
+    #[clap(long)]
+    keep_work_dirs_minutes: Option<u64>,
+    /// Do not remove cgroups, mounts and work directories left by previous
+    /// invoker runs on startup.
+    /// Use it if several invokers share work directory.
+    #[clap(long)]
+    skip_recovery: bool,
+    /// On SIGTERM or SIGINT, wait this many seconds for running requests
+    /// to finish before cancelling them. Defaults to 30.
+    #[clap(long)]
+    shutdown_grace_seconds: Option<u64>,
+}
+
+impl CliArgs {
+    /// Fills options which were not specified on the command line
+    /// from the config.
+    fn merge_config(&mut self, config: config::Config) -> anyhow::Result<()> {
+        if config.host_toolchains.is_some() {
+            tracing::warn!("config key host-toolchains is deprecated and ignored");
+        }
+        self.skip_checks |= config.skip_checks;
+        self.debug_leak_sandboxes |= config.debug_leak_sandboxes;
+        self.skip_recovery |= config.skip_recovery;
+        if self.work_dir.is_none() {
+            self.work_dir = config.work_dir;
+        }
+        if let (None, Some(addr)) = (&self.listen_address, config.listen_address) {
+            let addr = addr.parse().context("invalid listen-address in config")?;
+            self.listen_address = Some(addr);
+        }
+        if self.shim.is_none() {
+            self.shim = config.shim;
+        }
+        if let (None, Some(range)) = (&self.sandbox_id_range, config.sandbox_id_range) {
+            let range = range
+                .parse()
+                .context("invalid sandbox-id-range in config")?;
+            self.sandbox_id_range = Some(range);
+        }
+        // Debugging modes are exclusive, so config can only be used
+        // when neither is specified on the command line.
+        if self.interactive_debug_path.is_none() && self.interactive_debug_url.is_none() {
+            if config.interactive_debug_path.is_some() && config.interactive_debug_url.is_some() {
+                anyhow::bail!(
+                    "config can not specify both interactive-debug-path and interactive-debug-url"
+                );
+            }
+            self.interactive_debug_path = config.interactive_debug_path;
+            self.interactive_debug_url = config.interactive_debug_url;
+        }
+        if self.default_wall_time_multiplier.is_none() {
+            self.default_wall_time_multiplier = config.default_wall_time_multiplier;
+        }
+        if self.expose_rootfs_item.is_empty() {
+            self.expose_rootfs_item = config.expose_rootfs_items.unwrap_or_default();
+        }
+        if self.keep_failed_work_dirs.is_none() {
+            self.keep_failed_work_dirs = config.keep_failed_work_dirs;
+        }
+        if self.keep_work_dirs_minutes.is_none() {
+            self.keep_work_dirs_minutes = config.keep_work_dirs_minutes;
+        }
+        if self.shutdown_grace_seconds.is_none() {
+            self.shutdown_grace_seconds = config.shutdown_grace_seconds;
+        }
+        Ok(())
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -70,37 +171,82 @@ fn main() -> anyhow::Result<()> {
         .with_env_filter(EnvFilter::from_default_env())
         .with_span_events(FmtSpan::CLOSE)
         .init();
-    let args: CliArgs = Clap::parse();
+    let mut args: CliArgs = Clap::parse();
+    if let Some(path) = args.config.clone() {
+        let config = config::Config::load(&path)
+            .with_context(|| format!("failed to load config {}", path.display()))?;
+        args.merge_config(config)?;
+    }
     tracing::debug!(args = ?args);
+    let multiplier = args
+        .default_wall_time_multiplier
+        .unwrap_or(DEFAULT_WALL_TIME_MULTIPLIER);
+    if !(multiplier.is_finite() && multiplier > 0.0) {
+        anyhow::bail!("--default-wall-time-multiplier must be positive number");
+    }
     if args.debug_leak_sandboxes {
         tracing::warn!("dangerous --debug-leak-sandboxes flag was enabled");
     }
-    init::init()?;
-    real_main(args)
+    let handler_cfg = HandlerConfig {
+        work_dir: args
+            .work_dir
+            .clone()
+            .context("work directory must be specified using --work-dir or config")?,
+        retention: work_dirs::RetentionPolicy {
+            keep_failed: args.keep_failed_work_dirs.unwrap_or(0),
+            keep_for: args
+                .keep_work_dirs_minutes
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+            keep_all: false,
+        },
+    };
+    if args.skip_recovery || args.debug_leak_sandboxes {
+        tracing::info!("startup recovery is disabled");
+    } else {
+        recovery::recover(&handler_cfg.work_dir, &handler_cfg.retention);
+    }
+    let isolation = init::init()?;
+    isolation.report();
+    real_main(args, isolation, handler_cfg, multiplier)
 }
 
 #[tokio::main]
-async fn real_main(args: CliArgs) -> anyhow::Result<()> {
-    let handler_cfg = HandlerConfig {
-        work_dir: args.work_dir.clone(),
-    };
+async fn real_main(
+    args: CliArgs,
+    isolation: init::Isolation,
+    handler_cfg: HandlerConfig,
+    wall_time_multiplier: f64,
+) -> anyhow::Result<()> {
+    let listen_address = args
+        .listen_address
+        .clone()
+        .context("listen address must be specified using --listen-address or config")?;
 
     let interactive_debug_suspender = interactive_debug::Suspender::new(&args);
 
     let sandbox_cfg = SandboxGlobalSettings {
-        // TODO: add CLI arg
-        exposed_host_items: None,
-        skip_system_checks: args.skip_checks,
+        exposed_host_items: if args.expose_rootfs_item.is_empty() {
+            None
+        } else {
+            Some(args.expose_rootfs_item.clone())
+        },
+        // System checks would fail because of missing cgroups.
+        skip_system_checks: args.skip_checks || isolation.is_degraded(),
         override_id_range: args.sandbox_id_range.as_ref().map(|r| (r.low, r.high)),
         leak: args.debug_leak_sandboxes,
-        // TODO: revisit when rootless mode is added
-        allow_fallback_pid_limit: false,
+        allow_fallback_pid_limit: isolation.is_degraded(),
+        real_time_limit_multiplier: wall_time_multiplier,
         suspender: Arc::new(interactive_debug_suspender),
     };
-    let handler = Handler::new(handler_cfg, sandbox_cfg)
+    let error_store = Arc::new(error_store::ErrorStore::new());
+    let handler = Handler::new(handler_cfg, sandbox_cfg, error_store.clone())
         .await
         .context("failed to initialize handler")?;
     let shim = ShimClient::new(args.shim.as_deref()).context("failed to initialize shim client")?;
-    let server = server::Server::new(handler, shim);
-    server.serve(args.listen_address.clone()).await
+    let shutdown_grace = Duration::from_secs(
+        args.shutdown_grace_seconds
+            .unwrap_or(DEFAULT_SHUTDOWN_GRACE_SECONDS),
+    );
+    let server = server::Server::new(handler, shim, error_store, shutdown_grace);
+    server.serve(listen_address).await
 }
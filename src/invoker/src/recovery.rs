@@ -0,0 +1,273 @@
+//! Startup recovery: removes resources left by previous invoker runs
+//! (e.g. after a crash), so that they do not build up across restarts.
+//! Recovery is best-effort: failures are logged, but do not prevent startup.
+//!
+//! Only resources of this invoker instance are removed: cgroups are found
+//! using the list each sandbox records in its data directory (see
+//! `record_cgroups`), so cgroups of other invokers sharing the hierarchy
+//! are not touched.
+use crate::{init::CGROUP_PREFIX, work_dirs, work_dirs::RetentionPolicy};
+use anyhow::Context;
+use std::{
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// How many times removal of a cgroup is retried while its processes exit.
+const CGROUP_REMOVE_ATTEMPTS: usize = 20;
+const CGROUP_REMOVE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// File in the sandbox data directory, listing cgroups of the sandbox
+/// relative to their hierarchy root, one per line.
+const CGROUPS_FILE: &str = "cgroups";
+
+#[derive(Debug, Default)]
+struct Reclaimed {
+    cgroups: usize,
+    processes: usize,
+    mounts: usize,
+    work_dirs: usize,
+    kept_work_dirs: usize,
+}
+
+/// Records cgroups of the sandbox, so that recovery can remove them if
+/// invoker crashes. `pid` is a process running in the sandbox.
+pub fn record_cgroups(sandbox_data_dir: &Path, pid: libc::pid_t) -> anyhow::Result<()> {
+    let data = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+        .context("failed to read process cgroups")?;
+    let mut cgroups = Vec::new();
+    // each line has form `<hierarchy-id>:<controllers>:<path>`
+    for path in data.lines().filter_map(|line| line.splitn(3, ':').nth(2)) {
+        if Path::new(path).starts_with(CGROUP_PREFIX) && path != CGROUP_PREFIX {
+            let path = path.trim_start_matches('/');
+            if !cgroups.contains(&path) {
+                cgroups.push(path);
+            }
+        }
+    }
+    let contents = cgroups
+        .iter()
+        .map(|path| format!("{}\n", path))
+        .collect::<String>();
+    std::fs::write(sandbox_data_dir.join(CGROUPS_FILE), contents)
+        .context("failed to write cgroups list")
+}
+
+/// Removes sandbox cgroups, volume mounts and per-request directories.
+/// Must be called before namespaces are unshared, because mounts of the
+/// previous run are only visible in the original mount namespace.
+pub fn recover(work_dir: &Path, retention: &RetentionPolicy) {
+    let mut reclaimed = Reclaimed::default();
+    let request_dirs = match request_dirs(work_dir) {
+        Ok(dirs) => dirs,
+        Err(err) => {
+            tracing::warn!(error = %format_args!("{:#}", err), "failed to list work directory");
+            return;
+        }
+    };
+    // Processes are killed first, so that they do not keep mounts busy.
+    for dir in &request_dirs {
+        if let Err(err) = remove_recorded_cgroups(dir, &mut reclaimed) {
+            tracing::warn!(
+                path = %dir.display(),
+                error = %format_args!("{:#}", err),
+                "failed to remove stale cgroups"
+            );
+        }
+    }
+    if let Err(err) = unmount_all(work_dir, &mut reclaimed) {
+        tracing::warn!(error = %format_args!("{:#}", err), "failed to remove stale mounts");
+    }
+    if let Err(err) = remove_work_dirs(request_dirs, retention, &mut reclaimed) {
+        tracing::warn!(error = %format_args!("{:#}", err), "failed to remove stale work directories");
+    }
+    tracing::info!(
+        cgroups = reclaimed.cgroups,
+        processes = reclaimed.processes,
+        mounts = reclaimed.mounts,
+        work_dirs = reclaimed.work_dirs,
+        kept_work_dirs = reclaimed.kept_work_dirs,
+        "startup recovery finished"
+    );
+}
+
+/// Returns roots of all mounted cgroup hierarchies (one for cgroup v2,
+/// one per hierarchy for cgroup v1).
+fn hierarchy_roots() -> Vec<PathBuf> {
+    let base = Path::new("/sys/fs/cgroup");
+    if base.join("cgroup.controllers").exists() {
+        return vec![base.to_path_buf()];
+    }
+    child_dirs(base).unwrap_or_default()
+}
+
+fn child_dirs(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut children = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            children.push(entry.path());
+        }
+    }
+    Ok(children)
+}
+
+/// Returns per-request directories. Other entries are left intact.
+fn request_dirs(work_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !work_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut dirs = child_dirs(work_dir)?;
+    dirs.retain(|dir| {
+        dir.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.parse::<uuid::Uuid>().is_ok())
+    });
+    Ok(dirs)
+}
+
+/// Removes cgroups recorded by sandboxes of the request.
+fn remove_recorded_cgroups(request_dir: &Path, reclaimed: &mut Reclaimed) -> anyhow::Result<()> {
+    let sandboxes = request_dir.join("sandboxes");
+    if !sandboxes.is_dir() {
+        return Ok(());
+    }
+    let roots = hierarchy_roots();
+    for sandbox in child_dirs(&sandboxes).context("failed to list sandboxes")? {
+        let data = match std::fs::read_to_string(sandbox.join(CGROUPS_FILE)) {
+            Ok(data) => data,
+            // sandbox creation was interrupted or it did not have own cgroups
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).context("failed to read cgroups list"),
+        };
+        for cgroup in data.lines().map(Path::new) {
+            let absolute = Path::new("/").join(cgroup);
+            let is_sandbox_cgroup = absolute.starts_with(CGROUP_PREFIX)
+                && absolute != Path::new(CGROUP_PREFIX)
+                && cgroup
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)));
+            if !is_sandbox_cgroup {
+                anyhow::bail!("unexpected cgroup {} in the list", cgroup.display());
+            }
+            for root in &roots {
+                let path = root.join(cgroup);
+                if path.is_dir() {
+                    remove_cgroup(&path, reclaimed)
+                        .with_context(|| format!("failed to remove cgroup {}", path.display()))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Kills all processes in the cgroup and its descendants and removes them.
+fn remove_cgroup(cgroup: &Path, reclaimed: &mut Reclaimed) -> anyhow::Result<()> {
+    for child in child_dirs(cgroup).context("failed to list cgroups")? {
+        remove_cgroup(&child, reclaimed)?;
+    }
+    let procs =
+        std::fs::read_to_string(cgroup.join("cgroup.procs")).context("failed to list processes")?;
+    for pid in procs.lines().filter_map(|pid| pid.trim().parse().ok()) {
+        let pid = nix::unistd::Pid::from_raw(pid);
+        if nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL).is_ok() {
+            reclaimed.processes += 1;
+        }
+    }
+    // cgroup can not be removed until killed processes exit
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match std::fs::remove_dir(cgroup) {
+            Ok(()) => break,
+            Err(err)
+                if err.raw_os_error() == Some(libc::EBUSY) && attempt < CGROUP_REMOVE_ATTEMPTS =>
+            {
+                std::thread::sleep(CGROUP_REMOVE_INTERVAL);
+            }
+            Err(err) => return Err(err).context("rmdir failed"),
+        }
+    }
+    tracing::debug!(cgroup = %cgroup.display(), "removed stale cgroup");
+    reclaimed.cgroups += 1;
+    Ok(())
+}
+
+fn unmount_all(work_dir: &Path, reclaimed: &mut Reclaimed) -> anyhow::Result<()> {
+    for mount in work_dirs::mounts_under(work_dir)? {
+        nix::mount::umount2(&mount, nix::mount::MntFlags::MNT_DETACH)
+            .with_context(|| format!("failed to unmount {}", mount.display()))?;
+        tracing::debug!(mount = %mount.display(), "removed stale mount");
+        reclaimed.mounts += 1;
+    }
+    Ok(())
+}
+
+/// Removes request directories which are not covered by the retention
+/// policy. Directories of requests interrupted by the previous run can not
+/// be told apart from retained failed ones, so `keep_failed` most recently
+/// modified directories are kept, as well as directories modified within
+/// `keep_for`.
+fn remove_work_dirs(
+    dirs: Vec<PathBuf>,
+    retention: &RetentionPolicy,
+    reclaimed: &mut Reclaimed,
+) -> anyhow::Result<()> {
+    let now = SystemTime::now();
+    let mut dirs = dirs
+        .into_iter()
+        .map(|dir| {
+            let modified = std::fs::metadata(&dir)?.modified()?;
+            let age = now.duration_since(modified).unwrap_or_default();
+            Ok((dir, age))
+        })
+        .collect::<std::io::Result<Vec<_>>>()
+        .context("failed to get modification time")?;
+    dirs.sort_by_key(|(_, age)| *age);
+    for (i, (dir, age)) in dirs.into_iter().enumerate() {
+        let retained = i < retention.keep_failed || retention.keep_for.is_some_and(|d| age < d);
+        if retained {
+            tracing::debug!(path = %dir.display(), "keeping work directory");
+            reclaimed.kept_work_dirs += 1;
+            continue;
+        }
+        work_dirs::remove_dir(&dir)?;
+        tracing::debug!(path = %dir.display(), "removed stale work directory");
+        reclaimed.work_dirs += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remove_work_dirs, request_dirs, Reclaimed};
+    use crate::work_dirs::RetentionPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn test_retained_work_dirs_are_kept() {
+        let work_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_hyphenated().to_string());
+        std::fs::create_dir(&work_dir).unwrap();
+        std::fs::create_dir(work_dir.join("not-a-request")).unwrap();
+        let mut created = Vec::new();
+        for _ in 0..3 {
+            let dir = work_dir.join(uuid::Uuid::new_v4().to_hyphenated().to_string());
+            std::fs::create_dir(&dir).unwrap();
+            created.push(dir);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let retention = RetentionPolicy {
+            keep_failed: 1,
+            ..Default::default()
+        };
+        let mut reclaimed = Reclaimed::default();
+        remove_work_dirs(request_dirs(&work_dir).unwrap(), &retention, &mut reclaimed).unwrap();
+        assert_eq!(reclaimed.work_dirs, 2);
+        assert_eq!(reclaimed.kept_work_dirs, 1);
+        // the most recent directory is kept
+        assert!(created[2].exists());
+        assert!(work_dir.join("not-a-request").exists());
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+}
@@ -1,9 +1,22 @@
 use crate::{
+    error_store::ErrorStore,
     handler::Handler,
+    jobs::JobStore,
     shim::{ShimClient, ShimResponse},
+    shutdown::Shutdown,
 };
 use anyhow::Context;
-use std::{convert::Infallible, net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc};
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::{InvokeError, InvokeResponse},
+    jobs::{JobCreated, JobStatus},
+};
+use std::{
+    convert::Infallible, future::Future, net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc,
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 use warp::Filter;
 
 #[derive(Debug, Clone)]
@@ -36,108 +49,402 @@ impl FromStr for ListenAddress {
 
 type Resp = hyper::Response<hyper::Body>;
 
-async fn route_exec_inner(
-    handler: Arc<Handler>,
-    shim: Arc<ShimClient>,
+/// State shared by all routes
+struct ServerState {
+    handler: Handler,
+    shim: ShimClient,
+    jobs: Arc<JobStore>,
+    errors: Arc<ErrorStore>,
+    shutdown: Arc<Shutdown>,
+}
+
+/// Result of the request processing
+enum Outcome {
+    Completed(InvokeResponse),
+    /// Request was rejected by the shim
+    Rejected(ApiError),
+    Failed(InvokeError),
+}
+
+/// Preprocesses request using shim and executes it
+async fn process_request(
+    state: &ServerState,
     req: serde_json::Value,
-) -> anyhow::Result<Resp> {
-    let shim_response = shim
+    cancel: &CancellationToken,
+) -> Outcome {
+    let shim_response = match state
+        .shim
         .call(req)
         .await
-        .context("failed to preprocess request using shim")?;
+        .context("failed to preprocess request using shim")
+    {
+        Ok(r) => r,
+        Err(err) => return Outcome::Failed(make_invoke_error(state, &err, None, None)),
+    };
 
     let req = match shim_response {
         ShimResponse::Accept(r) => r,
-        ShimResponse::Reject(rej) => {
-            let response = serde_json::json!({
-                "error": "request rejected by the shim",
-                "details": rej
-            });
-            let response = serde_json::to_string(&response)?;
-            return Ok(hyper::Response::builder()
-                .status(400)
-                .body(response.into())
-                .expect("incorrect response"));
-        }
+        ShimResponse::Reject(rej) => return Outcome::Rejected(rej),
     };
 
-    let response = handler.handle_invoke_request(&req).await?;
-    let response = serde_json::to_vec(&response).context("failed to serialize InvokeResponse")?;
+    match state.handler.handle_invoke_request(&req, cancel).await {
+        Ok(response) => Outcome::Completed(response),
+        Err(err) => Outcome::Failed(make_invoke_error(
+            state,
+            &err.error,
+            err.step_index(),
+            err.partial_response,
+        )),
+    }
+}
+
+/// Logs and records error and converts it to the `InvokeError`
+fn make_invoke_error(
+    state: &ServerState,
+    err: &anyhow::Error,
+    step_index: Option<usize>,
+    partial_response: Option<InvokeResponse>,
+) -> InvokeError {
+    let error_id = uuid::Uuid::new_v4();
+    let message = format!("{:#}", err);
+
+    let api_error = err.chain().find_map(|e| e.downcast_ref::<ApiError>());
+    let code = api_error.map_or(ErrorCode::Internal, |e| e.code);
+
+    if code.is_user_error() {
+        tracing::info!(error = %message, error_id = %error_id.to_hyphenated(), "invalid request");
+    } else {
+        tracing::error!(
+            error = %message,
+            error_id = %error_id.to_hyphenated(),
+            "invocation request failed"
+        );
+    }
+    state.errors.record(error_id, err);
+    InvokeError {
+        error_id,
+        code,
+        problems: api_error.map(|e| e.problems.clone()).unwrap_or_default(),
+        error_chain: err.chain().map(|e| e.to_string()).collect(),
+        step_index,
+        partial_response,
+    }
+}
 
+fn json_response(status: u16, body: &impl serde::Serialize) -> anyhow::Result<Resp> {
+    let body = serde_json::to_vec(body).context("failed to serialize response")?;
     Ok(hyper::Response::builder()
-        .status(200)
-        .body(response.into())
+        .status(status)
+        .body(body.into())
         .expect("incorrect response"))
 }
 
+fn error_response(error: &InvokeError) -> Resp {
+    let body = match serde_json::to_vec(error) {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!(error = %err, "failed to serialize InvokeError");
+            Vec::new()
+        }
+    };
+    let status = if error.code.is_user_error() { 400 } else { 500 };
+    hyper::Response::builder()
+        .status(status)
+        .header("Error-UUID", error.error_id.to_hyphenated().to_string())
+        .body(body.into())
+        .expect("incorrect response")
+}
+
+/// Describes the shim rejection the same way as other request errors.
+fn rejection_error(state: &ServerState, rej: ApiError) -> InvokeError {
+    let err = anyhow::Error::new(rej).context("request rejected by the shim");
+    make_invoke_error(state, &err, None, None)
+}
+
+fn shutting_down_response() -> anyhow::Result<Resp> {
+    let response = serde_json::json!({
+        "error": "invoker is shutting down"
+    });
+    json_response(503, &response)
+}
+
+fn empty_response(status: u16) -> Resp {
+    hyper::Response::builder()
+        .status(status)
+        .body((&[] as &'static [u8]).into())
+        .expect("incorrect response")
+}
+
+/// Cancels the token when dropped
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+async fn route_exec_inner(state: Arc<ServerState>, req: serde_json::Value) -> anyhow::Result<Resp> {
+    let guard = match state.shutdown.begin_request() {
+        Some(guard) => guard,
+        None => return shutting_down_response(),
+    };
+    let cancel = guard.cancel.clone();
+    // If client closes the connection, this future is dropped.
+    // Request is processed in the separate task, so that it is able
+    // to observe cancellation and destroy sandboxes.
+    let _cancel_guard = CancelOnDrop(cancel.clone());
+    let task_state = state.clone();
+    let outcome = tokio::task::spawn(async move {
+        let outcome = process_request(&task_state, req, &cancel).await;
+        drop(guard);
+        outcome
+    })
+    .await
+    .context("request processing task panicked")?;
+    match outcome {
+        Outcome::Completed(response) => json_response(200, &response),
+        Outcome::Rejected(rej) => Ok(error_response(&rejection_error(&state, rej))),
+        Outcome::Failed(err) => Ok(error_response(&err)),
+    }
+}
+
 /// Handler for /exec requests
-#[tracing::instrument(skip(handler, shim, req))]
-async fn route_exec(
-    handler: Arc<Handler>,
-    shim: Arc<ShimClient>,
+#[tracing::instrument(skip(state, req))]
+async fn route_exec(state: Arc<ServerState>, req: serde_json::Value) -> Result<Resp, Infallible> {
+    let res = route_exec_inner(state.clone(), req).await;
+    match res {
+        Ok(response) => Ok(response),
+        Err(err) => Ok(error_response(&make_invoke_error(&state, &err, None, None))),
+    }
+}
+
+fn route_create_job_inner(state: Arc<ServerState>, req: serde_json::Value) -> anyhow::Result<Resp> {
+    let id = req
+        .get("id")
+        .and_then(|id| id.as_str())
+        .and_then(|id| id.parse::<uuid::Uuid>().ok());
+    let id = match id {
+        Some(id) => id,
+        None => {
+            let error = ApiError::new(
+                ErrorCode::InvalidRequest,
+                "request id is missing or invalid",
+            );
+            return json_response(400, &error);
+        }
+    };
+    let guard = match state.shutdown.begin_request() {
+        Some(guard) => guard,
+        None => return shutting_down_response(),
+    };
+    let cancel = guard.cancel.clone();
+    let job_cancel = cancel.clone();
+    let jobs = state.jobs.clone();
+    let job = async move {
+        let status = match process_request(&state, req, &job_cancel).await {
+            Outcome::Completed(response) => JobStatus::Finished(response),
+            Outcome::Rejected(rej) => JobStatus::Rejected(rej),
+            Outcome::Failed(err) => JobStatus::Failed(err),
+        };
+        drop(guard);
+        status
+    };
+    if !jobs.start(id, cancel, job) {
+        let error = ApiError::new(
+            ErrorCode::DuplicateRequestId,
+            "job with this id already exists",
+        );
+        return json_response(409, &error);
+    }
+    json_response(202, &JobCreated { id })
+}
+
+/// Handler for POST /jobs requests
+#[tracing::instrument(skip(state, req))]
+async fn route_create_job(
+    state: Arc<ServerState>,
     req: serde_json::Value,
 ) -> Result<Resp, Infallible> {
-    let res = route_exec_inner(handler, shim, req).await;
-    match res {
+    match route_create_job_inner(state.clone(), req) {
         Ok(response) => Ok(response),
-        Err(err) => {
-            let error_id = uuid::Uuid::new_v4();
-            let err = format!("{:#}", err);
+        Err(err) => Ok(error_response(&make_invoke_error(&state, &err, None, None))),
+    }
+}
+
+/// Handler for GET /jobs/{id} requests
+async fn route_get_job(state: Arc<ServerState>, id: Uuid) -> Result<Resp, Infallible> {
+    let response = state.jobs.with_info(id, |info| json_response(200, info));
+    match response {
+        Some(Ok(response)) => Ok(response),
+        Some(Err(err)) => Ok(error_response(&make_invoke_error(&state, &err, None, None))),
+        None => Ok(empty_response(404)),
+    }
+}
+
+/// Handler for DELETE /jobs/{id} requests
+async fn route_delete_job(state: Arc<ServerState>, id: Uuid) -> Result<Resp, Infallible> {
+    if !state.jobs.remove(id) {
+        return Ok(empty_response(404));
+    }
+    Ok(empty_response(204))
+}
+
+/// Handler for POST /cancel/{id} requests
+async fn route_cancel(state: Arc<ServerState>, id: Uuid) -> Result<Resp, Infallible> {
+    if !state.handler.cancel(id) {
+        return Ok(empty_response(404));
+    }
+    Ok(empty_response(202))
+}
 
-            tracing::error!(error = %err, error_id = %error_id.to_hyphenated(), "invocation request failed");
+/// Handler for GET /errors/{id} requests
+async fn route_get_error(state: Arc<ServerState>, id: Uuid) -> Result<Resp, Infallible> {
+    let report = match state.errors.get(id) {
+        Some(r) => r,
+        None => return Ok(empty_response(404)),
+    };
+    match json_response(200, &report) {
+        Ok(response) => Ok(response),
+        Err(err) => Ok(error_response(&make_invoke_error(&state, &err, None, None))),
+    }
+}
 
-            Ok(hyper::Response::builder()
-                .status(500)
-                .header("Error-UUID", error_id.to_hyphenated().to_string())
-                .body((&[] as &'static [u8]).into())
-                .expect("incorrect response"))
+/// Handler for GET /openapi.json and GET /schema.json requests
+async fn route_schema(schema: Arc<serde_json::Value>) -> Result<Resp, Infallible> {
+    match json_response(200, &*schema) {
+        Ok(response) => Ok(response),
+        Err(err) => {
+            tracing::error!(error = %err, "failed to serialize schema");
+            Ok(empty_response(500))
         }
     }
 }
 
 /// Handler for /ready requests
-async fn route_ready() -> Result<&'static str, Infallible> {
-    Ok("OK")
+async fn route_ready(state: Arc<ServerState>) -> Result<Resp, Infallible> {
+    if state.shutdown.is_draining() {
+        return Ok(empty_response(503));
+    }
+    Ok(hyper::Response::new("OK".into()))
 }
 
 /// Server HTTP API.
 pub struct Server {
-    handler: Arc<Handler>,
-    shim: Arc<ShimClient>,
+    state: Arc<ServerState>,
+    /// How long running requests are waited for on shutdown.
+    shutdown_grace: Duration,
 }
 
 impl Server {
-    pub fn new(handler: Handler, shim: ShimClient) -> Self {
+    pub fn new(
+        handler: Handler,
+        shim: ShimClient,
+        errors: Arc<ErrorStore>,
+        shutdown_grace: Duration,
+    ) -> Self {
         Server {
-            handler: Arc::new(handler),
-            shim: Arc::new(shim),
+            state: Arc::new(ServerState {
+                handler,
+                shim,
+                jobs: Arc::new(JobStore::new()),
+                errors,
+                shutdown: Shutdown::new(),
+            }),
+            shutdown_grace,
         }
     }
 
+    /// Resolves when the server should stop: shutdown was requested using
+    /// a signal and all requests are finished.
+    async fn shutdown(state: Arc<ServerState>, grace: Duration, signal: impl Future<Output = ()>) {
+        signal.await;
+        state.shutdown.drain(grace).await;
+        state.handler.shutdown().await;
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn serve(self, addr: ListenAddress) -> anyhow::Result<()> {
-        let handler = self.handler.clone();
-        let shim = self.shim.clone();
+        let state = self.state.clone();
         let r_exec = warp::path("exec")
             .and(warp::filters::body::json())
-            .and_then(move |req| route_exec(handler.clone(), shim.clone(), req));
-        let r_ready = warp::path("ready").and_then(route_ready);
+            .and_then(move |req| route_exec(state.clone(), req));
+        let state = self.state.clone();
+        let r_ready = warp::path("ready").and_then(move || route_ready(state.clone()));
+        let state = self.state.clone();
+        let r_create_job = warp::path!("jobs")
+            .and(warp::post())
+            .and(warp::filters::body::json())
+            .and_then(move |req| route_create_job(state.clone(), req));
+        let state = self.state.clone();
+        let r_get_job = warp::path!("jobs" / Uuid)
+            .and(warp::get())
+            .and_then(move |id| route_get_job(state.clone(), id));
+        let state = self.state.clone();
+        let r_delete_job = warp::path!("jobs" / Uuid)
+            .and(warp::delete())
+            .and_then(move |id| route_delete_job(state.clone(), id));
+        let state = self.state.clone();
+        let r_cancel = warp::path!("cancel" / Uuid)
+            .and(warp::post())
+            .and_then(move |id| route_cancel(state.clone(), id));
+        let state = self.state.clone();
+        let r_get_error = warp::path!("errors" / Uuid)
+            .and(warp::get())
+            .and_then(move |id| route_get_error(state.clone(), id));
+        let openapi = Arc::new(invoker_api::schema::openapi());
+        let r_openapi = warp::path!("openapi.json")
+            .and(warp::get())
+            .and_then(move || route_schema(openapi.clone()));
+        let json_schema = Arc::new(invoker_api::schema::json_schema());
+        let r_json_schema = warp::path!("schema.json")
+            .and(warp::get())
+            .and_then(move || route_schema(json_schema.clone()));
         #[cfg(debug_assertions)]
         let r_exec = r_exec.boxed();
         #[cfg(debug_assertions)]
         let r_ready = r_ready.boxed();
+        #[cfg(debug_assertions)]
+        let r_create_job = r_create_job.boxed();
+        #[cfg(debug_assertions)]
+        let r_get_job = r_get_job.boxed();
+        #[cfg(debug_assertions)]
+        let r_delete_job = r_delete_job.boxed();
+        #[cfg(debug_assertions)]
+        let r_cancel = r_cancel.boxed();
+        #[cfg(debug_assertions)]
+        let r_get_error = r_get_error.boxed();
+        #[cfg(debug_assertions)]
+        let r_openapi = r_openapi.boxed();
+        #[cfg(debug_assertions)]
+        let r_json_schema = r_json_schema.boxed();
 
-        let srv = r_exec.or(r_ready);
+        let srv = r_exec
+            .or(r_ready)
+            .or(r_create_job)
+            .or(r_get_job)
+            .or(r_delete_job)
+            .or(r_cancel)
+            .or(r_get_error)
+            .or(r_openapi)
+            .or(r_json_schema);
         let srv = warp::serve(srv);
+        let signal =
+            crate::shutdown::termination_signals().context("failed to setup signal handlers")?;
+        let shutdown = Self::shutdown(self.state.clone(), self.shutdown_grace, signal);
         match addr {
             ListenAddress::Tcp(addr) => {
-                srv.run(addr).await;
+                let (_, server) = srv
+                    .try_bind_with_graceful_shutdown(addr, shutdown)
+                    .with_context(|| format!("failed to bind to {}", addr))?;
+                server.await;
             }
             ListenAddress::Uds(path) => {
                 let listener = tokio::net::UnixListener::bind(&path)
                     .with_context(|| format!("failed to attach to UDS {}", path.display()))?;
                 let listener = tokio_stream::wrappers::UnixListenerStream::new(listener);
-                srv.run_incoming(listener).await;
+                srv.serve_incoming_with_graceful_shutdown(listener, shutdown)
+                    .await;
             }
         }
 
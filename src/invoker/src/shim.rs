@@ -1,6 +1,7 @@
 //! Shim client
 
 use anyhow::Context;
+use invoker_api::error::{ApiError, ErrorCode};
 
 struct HttpShim {
     client: reqwest::Client,
@@ -17,9 +18,9 @@ pub struct ShimClient {
 pub enum ShimResponse {
     /// Shim accepted the request and modified it
     Accept(invoker_api::invoke::InvokeRequest),
-    /// Shim rejected the request, and provided value
+    /// Shim rejected the request, and provided error
     /// that should be returned to the user
-    Reject(serde_json::Value),
+    Reject(ApiError),
 }
 
 impl ShimClient {
@@ -46,9 +47,13 @@ impl ShimClient {
             Some(h) => h,
             None => {
                 tracing::info!("Shim not configured");
-                return Ok(ShimResponse::Accept(serde_json::from_value(val).context(
-                    "no shim enabled, but incoming request is not valid InvokeRequest",
-                )?));
+                return Ok(match serde_json::from_value(val) {
+                    Ok(req) => ShimResponse::Accept(req),
+                    Err(err) => ShimResponse::Reject(ApiError::new(
+                        ErrorCode::InvalidRequest,
+                        format!("request is not valid InvokeRequest: {}", err),
+                    )),
+                });
             }
         };
         let uri = format!("{}/on-request", h.base);
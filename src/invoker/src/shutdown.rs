@@ -0,0 +1,141 @@
+//! Graceful shutdown: after SIGTERM or SIGINT new requests are rejected,
+//! running requests are given some time to finish, and the remaining ones
+//! are cancelled.
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::watch,
+};
+use tokio_util::sync::CancellationToken;
+
+struct State {
+    draining: bool,
+    in_flight: usize,
+}
+
+/// Keeps track of running requests.
+pub struct Shutdown {
+    state: Mutex<State>,
+    /// Receives number of running requests on each change.
+    in_flight_tx: watch::Sender<usize>,
+    in_flight_rx: watch::Receiver<usize>,
+    /// Parent of all request cancellation tokens.
+    abort: CancellationToken,
+}
+
+/// Must be held while request is processed.
+pub struct RequestGuard {
+    shutdown: Arc<Shutdown>,
+    /// Triggered when request must be cancelled because of shutdown.
+    pub cancel: CancellationToken,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        let mut state = self.shutdown.state.lock().unwrap();
+        state.in_flight -= 1;
+        self.shutdown.in_flight_tx.send(state.in_flight).ok();
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Arc<Self> {
+        let (in_flight_tx, in_flight_rx) = watch::channel(0);
+        Arc::new(Shutdown {
+            state: Mutex::new(State {
+                draining: false,
+                in_flight: 0,
+            }),
+            in_flight_tx,
+            in_flight_rx,
+            abort: CancellationToken::new(),
+        })
+    }
+
+    /// Returns None if shutdown was requested and request must be rejected.
+    pub fn begin_request(self: &Arc<Self>) -> Option<RequestGuard> {
+        let mut state = self.state.lock().unwrap();
+        if state.draining {
+            return None;
+        }
+        state.in_flight += 1;
+        self.in_flight_tx.send(state.in_flight).ok();
+        Some(RequestGuard {
+            shutdown: self.clone(),
+            cancel: self.abort.child_token(),
+        })
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.state.lock().unwrap().draining
+    }
+
+    async fn wait_idle(&self) {
+        let mut rx = self.in_flight_rx.clone();
+        while *rx.borrow() != 0 {
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Stops accepting requests and waits until running requests are finished.
+    /// Requests which are still running after `grace` are cancelled.
+    pub async fn drain(&self, grace: Duration) {
+        let in_flight = {
+            let mut state = self.state.lock().unwrap();
+            state.draining = true;
+            state.in_flight
+        };
+        tracing::info!(
+            requests = in_flight,
+            grace_seconds = grace.as_secs(),
+            "shutting down, waiting for running requests"
+        );
+        if tokio::time::timeout(grace, self.wait_idle()).await.is_err() {
+            tracing::warn!(
+                requests = *self.in_flight_rx.borrow(),
+                "grace period expired, cancelling running requests"
+            );
+            self.abort.cancel();
+            self.wait_idle().await;
+        }
+        tracing::info!("all requests finished");
+    }
+}
+
+/// Subscribes to SIGTERM and SIGINT.
+pub fn termination_signals() -> std::io::Result<impl std::future::Future<Output = ()>> {
+    let sigterm = signal(SignalKind::terminate())?;
+    let sigint = signal(SignalKind::interrupt())?;
+    Ok(wait_for_signal(sigterm, sigint))
+}
+
+async fn wait_for_signal(mut sigterm: Signal, mut sigint: Signal) {
+    tokio::select! {
+        _ = sigterm.recv() => tracing::info!("received SIGTERM"),
+        _ = sigint.recv() => tracing::info!("received SIGINT"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shutdown;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_drain_cancels_requests_after_grace_period() {
+        let shutdown = Shutdown::new();
+        let guard = shutdown.begin_request().unwrap();
+        let request = tokio::task::spawn(async move {
+            guard.cancel.cancelled().await;
+        });
+        shutdown.drain(Duration::from_millis(10)).await;
+        assert!(request.await.is_ok());
+        assert!(shutdown.is_draining());
+        assert!(shutdown.begin_request().is_none());
+    }
+}
@@ -0,0 +1,27 @@
+//! Fixtures shared by unit tests.
+use invoker_api::invoke::{Action, FileId, InvokeRequest, Step};
+
+/// Returns a step with the given id, which opens a null file.
+pub fn step(stage: u32, id: &str, depends_on: &[&str]) -> Step {
+    Step {
+        stage,
+        action: Action::OpenNullFile {
+            id: FileId(id.to_string()),
+        },
+        id: Some(id.to_string()),
+        depends_on: depends_on.iter().map(|s| s.to_string()).collect(),
+        condition: None,
+        ext: Default::default(),
+    }
+}
+
+pub fn request(steps: Vec<Step>) -> InvokeRequest {
+    InvokeRequest {
+        steps,
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+        id: uuid::Uuid::nil(),
+        budget: None,
+        ext: Default::default(),
+    }
+}
@@ -0,0 +1,231 @@
+//! Manages per-request work directories (`<work-dir>/<request-id>`).
+//! Directory is removed after the request is finished, unless retention
+//! policy says it should be kept for debugging.
+use anyhow::Context;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// Specifies which work directories are kept after request is finished.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Number of most recent failed requests whose directories are kept.
+    pub keep_failed: usize,
+    /// Directories are removed only after this time since request finish.
+    pub keep_for: Option<Duration>,
+    /// Never remove directories (used together with leaking sandboxes,
+    /// because leaked sandboxes still use their directories).
+    pub keep_all: bool,
+}
+
+struct Retained {
+    id: uuid::Uuid,
+    generation: u64,
+    finished_at: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    /// Generation of each existing directory. It is changed when the
+    /// directory is reused by request with the same id, so that earlier
+    /// scheduled removal does not affect new request.
+    generations: HashMap<uuid::Uuid, u64>,
+    next_generation: u64,
+    /// Retained directories of failed requests, oldest first.
+    failed: VecDeque<Retained>,
+}
+
+pub struct WorkDirs {
+    root: PathBuf,
+    policy: RetentionPolicy,
+    /// Also held while directories are removed.
+    state: Mutex<State>,
+}
+
+impl WorkDirs {
+    pub fn new(root: PathBuf, policy: RetentionPolicy) -> Arc<Self> {
+        Arc::new(WorkDirs {
+            root,
+            policy,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    fn path(&self, id: uuid::Uuid) -> PathBuf {
+        self.root.join(id.to_hyphenated().to_string())
+    }
+
+    /// Returns work directory for the request.
+    /// Directory left by the previous request with the same id is removed.
+    pub async fn acquire(&self, id: uuid::Uuid) -> anyhow::Result<PathBuf> {
+        let path = self.path(id);
+        let mut state = self.state.lock().await;
+        let generation = state.next_generation;
+        state.next_generation += 1;
+        state.generations.insert(id, generation);
+        state.failed.retain(|retained| retained.id != id);
+        if path.exists() {
+            tracing::info!(path = %path.display(), "removing work directory of previous request");
+            remove_in_background(path.clone()).await?;
+        }
+        Ok(path)
+    }
+
+    /// Must be called when request is finished and all its sandboxes
+    /// and volumes are destroyed.
+    pub async fn release(self: &Arc<Self>, id: uuid::Uuid, failed: bool) {
+        if self.policy.keep_all {
+            tracing::info!("keeping work directory because sandboxes are leaked");
+            return;
+        }
+        let mut state = self.state.lock().await;
+        let generation = match state.generations.get(&id) {
+            Some(&generation) => generation,
+            None => return,
+        };
+        let finished_at = Instant::now();
+        if failed && self.policy.keep_failed > 0 {
+            state.failed.push_back(Retained {
+                id,
+                generation,
+                finished_at,
+            });
+            if state.failed.len() <= self.policy.keep_failed {
+                tracing::info!("keeping work directory of failed request");
+                return;
+            }
+            let evicted = state.failed.pop_front().expect("queue is not empty");
+            self.schedule_removal(evicted.id, evicted.generation, evicted.finished_at);
+        } else {
+            self.schedule_removal(id, generation, finished_at);
+        }
+    }
+
+    /// Removes directories whose removal was scheduled, but was not performed
+    /// yet. Used on shutdown, because scheduled removals do not survive it.
+    /// Directories kept for debugging are left for the startup recovery.
+    pub async fn flush(&self) {
+        if self.policy.keep_all || self.policy.keep_for.is_some() {
+            return;
+        }
+        let mut state = self.state.lock().await;
+        let retained = state
+            .failed
+            .iter()
+            .map(|retained| retained.id)
+            .collect::<HashSet<_>>();
+        let pending = state
+            .generations
+            .keys()
+            .filter(|id| !retained.contains(id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in pending {
+            state.generations.remove(&id);
+            let path = self.path(id);
+            if !path.exists() {
+                continue;
+            }
+            tracing::debug!(path = %path.display(), "removing work directory");
+            if let Err(err) = remove_in_background(path).await {
+                tracing::warn!(error = %format_args!("{:#}", err), "failed to remove work directory");
+            }
+        }
+    }
+
+    fn schedule_removal(self: &Arc<Self>, id: uuid::Uuid, generation: u64, finished_at: Instant) {
+        let deadline = finished_at + self.policy.keep_for.unwrap_or_default();
+        let this = self.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep_until(deadline.into()).await;
+            let mut state = this.state.lock().await;
+            if state.generations.get(&id) != Some(&generation) {
+                // directory was reused
+                return;
+            }
+            state.generations.remove(&id);
+            let path = this.path(id);
+            tracing::debug!(path = %path.display(), "removing work directory");
+            if let Err(err) = remove_in_background(path).await {
+                tracing::warn!(error = %format_args!("{:#}", err), "failed to remove work directory");
+            }
+        });
+    }
+}
+
+async fn remove_in_background(path: PathBuf) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || remove_dir(&path))
+        .await
+        .context("cleanup task panicked")?
+}
+
+/// Removes directory, detaching all filesystems mounted inside it first,
+/// so that their contents are not removed.
+pub fn remove_dir(path: &Path) -> anyhow::Result<()> {
+    for mount in mounts_under(path)? {
+        tracing::debug!(mount = %mount.display(), "detaching mount");
+        nix::mount::umount2(&mount, nix::mount::MntFlags::MNT_DETACH)
+            .with_context(|| format!("failed to unmount {}", mount.display()))?;
+    }
+    std::fs::remove_dir_all(path).with_context(|| format!("failed to remove {}", path.display()))
+}
+
+/// Returns mount points located inside `dir`, most nested first.
+pub fn mounts_under(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mountinfo =
+        std::fs::read_to_string("/proc/self/mountinfo").context("failed to read mountinfo")?;
+    let mut mounts = mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape_mount_point)
+        .filter(|mount| mount.starts_with(dir) && mount != dir)
+        .collect::<Vec<_>>();
+    mounts.sort_by_key(|mount| std::cmp::Reverse(mount.components().count()));
+    mounts.dedup();
+    Ok(mounts)
+}
+
+/// Mountinfo escapes spaces and some other characters as `\NNN` (octal).
+fn unescape_mount_point(s: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                out.push(code);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    std::ffi::OsString::from_vec(out).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unescape_mount_point;
+    use std::path::Path;
+
+    #[test]
+    fn test_unescape_mount_point() {
+        assert_eq!(
+            unescape_mount_point(r"/var/judges/a\040b\134c"),
+            Path::new(r"/var/judges/a b\c")
+        );
+        assert_eq!(unescape_mount_point(r"/x\0"), Path::new(r"/x\0"));
+    }
+}
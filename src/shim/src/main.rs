@@ -4,7 +4,11 @@ mod transform;
 
 use clap::Clap;
 use futures::future::TryFutureExt;
-use invoker_api::{invoke::InvokeRequest, shims::ShimResponse};
+use invoker_api::{
+    error::{ApiError, ErrorCode},
+    invoke::InvokeRequest,
+    shims::ShimResponse,
+};
 use std::{
     convert::Infallible,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
@@ -26,13 +30,31 @@ async fn route_on_request_inner(
     state: Arc<ServerState>,
     mut req: InvokeRequest,
 ) -> anyhow::Result<ShimResponse> {
-    transform::transform_request(
+    if let Err(err) = invoker_api::validate::check(&req) {
+        return Ok(ShimResponse::Error(err));
+    }
+    let res = transform::transform_request(
         &mut req,
         state.toolchain_puller.clone(),
         &state.local_exchange_dir,
         &state.invoker_exchange_dir,
     )
-    .await?;
+    .await;
+    if let Err(err) = res {
+        // errors caused by the request itself are reported to the user,
+        // other errors are shim faults.
+        let code = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<ApiError>())
+            .map(|e| e.code);
+        return match code {
+            Some(code) => Ok(ShimResponse::Error(ApiError::new(
+                code,
+                format!("{:#}", err),
+            ))),
+            None => Err(err),
+        };
+    }
 
     Ok(ShimResponse::Result(req))
 }
@@ -44,10 +66,10 @@ async fn route_on_request(
     let req = match serde_json::from_value(req) {
         Ok(r) => r,
         Err(err) => {
-            return Ok(ShimResponse::Error(serde_json::Value::String(format!(
-                "invalid request body: parse error: {:#}",
-                err
-            ))));
+            return Ok(ShimResponse::Error(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("invalid request body: parse error: {:#}", err),
+            )));
         }
     };
     route_on_request_inner(state, req).await
@@ -58,7 +80,7 @@ async fn route_ready() -> Result<&'static str, Infallible> {
 }
 
 #[derive(Debug)]
-struct AnyhowRejection(anyhow::Error);
+struct AnyhowRejection(#[allow(dead_code)] anyhow::Error);
 
 impl warp::reject::Reject for AnyhowRejection {}
 
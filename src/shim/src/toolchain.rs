@@ -34,9 +34,7 @@ pub struct ImageConfig {
 
 impl ImageConfig {
     fn parse_env_item(item: &str) -> Option<(String, String)> {
-        let mut parts = item.splitn(2, '=');
-        let key = parts.next()?;
-        let value = parts.next()?;
+        let (key, value) = item.split_once('=')?;
         Some((key.to_string(), value.to_string()))
     }
 
@@ -2,6 +2,7 @@
 use crate::toolchain::{PulledToolchain, ToolchainPuller};
 use anyhow::Context as _;
 use invoker_api::{
+    error::{ApiError, ErrorCode},
     invoke::{
         Action, Command, EnvVarValue, EnvironmentVariable, Extensions, InputSource, InvokeRequest,
         PathPrefix, PrefixedPath, SandboxSettings,
@@ -19,6 +20,10 @@ use std::{
     sync::Arc,
 };
 
+fn invalid_extensions(message: String) -> ApiError {
+    ApiError::new(ErrorCode::InvalidExtensions, message)
+}
+
 fn take_ext(ext: &mut Extensions) -> serde_json::Value {
     serde_json::Value::Object(std::mem::take(&mut ext.0))
 }
@@ -39,8 +44,8 @@ pub(crate) async fn transform_request(
     local_exchange_dir: &Path,
     invoker_exchange_dir: &Path,
 ) -> anyhow::Result<()> {
-    let exts: RequestExtensions =
-        serde_json::from_value(take_ext(&mut req.ext)).context("invalid request extensions")?;
+    let exts: RequestExtensions = serde_json::from_value(take_ext(&mut req.ext))
+        .map_err(|err| invalid_extensions(format!("invalid request extensions: {}", err)))?;
 
     let local_extra_files_dir = local_exchange_dir
         .join("extra")
@@ -61,7 +66,10 @@ pub(crate) async fn transform_request(
 
     for (k, v) in &exts.extra_files {
         if k.starts_with('/') || k.starts_with('\\') {
-            anyhow::bail!("extraFiles.map specifies absolute path {}", k);
+            anyhow::bail!(ApiError::new(
+                ErrorCode::PathNotRelative,
+                format!("extraFiles.map specifies absolute path {}", k)
+            ));
         }
         let path = local_extra_files_dir.join(k);
         let contents = load_input(&v.contents)
@@ -140,10 +148,12 @@ struct TooclhainsUtil {
 
 impl TooclhainsUtil {
     fn update_command(&self, cmd: &mut Command) -> anyhow::Result<()> {
-        let image = self
-            .sandbox_images
-            .get(&cmd.sandbox_name)
-            .with_context(|| format!("command references unknown sandbox {}", cmd.sandbox_name))?;
+        let image = self.sandbox_images.get(&cmd.sandbox_name).ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::UnknownSandbox,
+                format!("command references unknown sandbox {}", cmd.sandbox_name),
+            )
+        })?;
         let pulled = &self.toolchains[image];
         let mut used_names = HashSet::new();
         for var in &cmd.env {
@@ -170,7 +180,7 @@ impl TooclhainsUtil {
             tracing::info!(image_name = image, "Pulling toolchain");
             let pulled = self
                 .puller
-                .resolve(&image)
+                .resolve(image)
                 .await
                 .context("failed to pull image")?;
             self.toolchains.insert(image.to_string(), pulled.clone());
@@ -203,12 +213,16 @@ fn make_relative(path: &Path) -> PathBuf {
 
 fn rewrite_prefixed_path(path: &mut PrefixedPath, extra_files_dir: &Path) -> anyhow::Result<()> {
     if let PathPrefix::Extension(ext) = &mut path.prefix {
-        let ext: SharedDirExtensionSource = serde_json::from_value(take_ext(ext))?;
+        let ext: SharedDirExtensionSource = serde_json::from_value(take_ext(ext))
+            .map_err(|err| invalid_extensions(format!("invalid sharedDirSource: {}", err)))?;
         if ext.name == EXTRA_FILES_DIR_NAME {
             path.prefix = PathPrefix::Host;
             path.path = make_relative(&extra_files_dir.join(&path.path));
         } else {
-            anyhow::bail!("unknown prefix name: {}", ext.name);
+            anyhow::bail!(invalid_extensions(format!(
+                "unknown prefix name: {}",
+                ext.name
+            )));
         }
     }
     Ok(())
@@ -222,9 +236,14 @@ async fn transform_sandbox(
     tcx: &mut TooclhainsUtil,
 ) -> anyhow::Result<()> {
     let exts: SandboxSettingsExtensions = serde_json::from_value(take_ext(&mut sandbox.ext))
-        .context("invalid sandbox settings extensions")?;
+        .map_err(|err| {
+            invalid_extensions(format!("invalid sandbox settings extensions: {}", err))
+        })?;
     if sandbox.base_image != Path::new("") {
-        anyhow::bail!("baseImage must be empty");
+        anyhow::bail!(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "baseImage must be empty"
+        ));
     }
     sandbox.base_image = tcx.pull_if_needed(&exts.image, &sandbox.name).await?;
 
@@ -239,7 +258,8 @@ async fn transform_command(
     tcx: &TooclhainsUtil,
     interp_dict: &HashMap<String, String>,
 ) -> anyhow::Result<()> {
-    *command = crate::interp::interpolate_command(command, interp_dict)?;
+    *command = crate::interp::interpolate_command(command, interp_dict)
+        .map_err(|err| ApiError::new(ErrorCode::InvalidRequest, err.to_string()))?;
     tcx.update_command(command)?;
 
     Ok(())
@@ -3,7 +3,7 @@ mod env;
 use anyhow::Context;
 use clap::Clap;
 use env::Env;
-use invoker_api::invoke::{Action, Extensions, InvokeRequest, InvokeResponse, OutputData};
+use invoker_api::invoke::{Action, Extensions, InvokeRequest, InvokeResponse};
 use rand::Rng;
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
@@ -145,10 +145,6 @@ fn wait() {
 }
 
 fn run_test(test_name: &str, test_case: &Path, port: u16) -> anyhow::Result<()> {
-    let client = reqwest::blocking::ClientBuilder::new()
-        .timeout(std::time::Duration::from_secs(100))
-        .build()?;
-    let addr = format!("http://localhost:{}/exec", port);
     let invoke_request =
         std::fs::read(test_case.join("request.yaml")).context("failed to read request.yaml")?;
     let mut invoke_request: invoker_api::invoke::InvokeRequest =
@@ -167,32 +163,33 @@ fn run_test(test_name: &str, test_case: &Path, port: u16) -> anyhow::Result<()>
             sb.ext = Extensions::make(&sandbox_settings_extensions)?;
         }
     }
-    let request_body = serde_json::to_string_pretty(&invoke_request)?;
-    let response = client
-        .post(addr.as_str())
-        .body(request_body)
-        .send()
-        .context("request failed")?;
-    if response.status().is_client_error() {
-        let response = response.text()?;
-        anyhow::bail!("request failed:\n{}", response);
-    } else if response.status().is_server_error() {
-        anyhow::bail!("invocation fault")
-    } else {
-        let response: InvokeResponse = response
-            .json()
-            .context("failed to deserialize response body")?;
-        let export_path = randomize(&format!("/tmp/jjs-invoker-test-{}-outputs", test_name));
-        let export_path: PathBuf = export_path.into();
-        std::fs::create_dir(&export_path)?;
-        export_response(&invoke_request, &response, &export_path)?;
-        {
-            let test_case = test_case.canonicalize()?;
-            let _d = xshell::pushd(&export_path)?;
-            xshell::cmd!("python3 {test_case}/validate.py")
-                .run()
-                .context("validation script failed")?;
-        }
+    let client =
+        invoker_client::Client::new(invoker_client::Address::Tcp(([127, 0, 0, 1], port).into()));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let response = runtime.block_on(async {
+        tokio::time::timeout(
+            std::time::Duration::from_secs(100),
+            client.exec(&invoke_request),
+        )
+        .await
+    });
+    let response = match response {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => anyhow::bail!("request failed:\n{:#}", anyhow::Error::new(err)),
+        Err(_) => anyhow::bail!("request timed out"),
+    };
+    let export_path = randomize(&format!("/tmp/jjs-invoker-test-{}-outputs", test_name));
+    let export_path: PathBuf = export_path.into();
+    std::fs::create_dir(&export_path)?;
+    export_response(&invoke_request, &response, &export_path)?;
+    {
+        let test_case = test_case.canonicalize()?;
+        let _d = xshell::pushd(&export_path)?;
+        xshell::cmd!("python3 {test_case}/validate.py")
+            .run()
+            .context("validation script failed")?;
     }
     Ok(())
 }
@@ -204,11 +201,9 @@ fn export_response(req: &InvokeRequest, res: &InvokeResponse, path: &Path) -> an
     for (req_out, res_out) in request_outputs.iter().zip(response_outputs.iter()) {
         let output_name = req_out.name.clone();
         println!("Exporting output {}", output_name);
-        let output_value = match &res_out.data {
-            OutputData::InlineBase64(v) => v,
-            OutputData::None => anyhow::bail!("missing output"),
-        };
-        let output_value = base64::decode(output_value).context("invalid base64")?;
+        let output_value = invoker_client::decode_output(&res_out.data)
+            .context("invalid base64")?
+            .context("missing output")?;
         std::fs::write(path.join(output_name), output_value)?;
     }
     Ok(())